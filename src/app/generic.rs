@@ -0,0 +1,16 @@
+use winit::event_loop::EventLoop;
+
+use crate::command::{Command, MenuEntry};
+
+
+pub struct App;
+
+impl App {
+    /// there is no status bar menu here
+    pub fn set_menu(&mut self, _menu: &[MenuEntry]) {}
+    pub fn commands(&mut self) -> Vec<Command> { Vec::new() }
+}
+
+pub fn init<E>(_event_loop: &EventLoop<E>) -> anyhow::Result<App> {
+    Ok(App)
+}
@@ -0,0 +1,185 @@
+use std::sync::mpsc;
+
+use icrate::{
+    AppKit::{
+        NSApplication, NSImage, NSMenu, NSMenuItem, NSScreen,
+        NSStatusBar, NSStatusItem, self,
+    },
+    Foundation::{MainThreadMarker, NSString, ns_string},
+};
+use objc2::{
+    declare_class, msg_send_id, mutability, sel,
+    rc::Id,
+    runtime::{AnyObject, NSObject, NSObjectProtocol},
+    ClassType, DeclaredClass,
+};
+use winit::{
+    event_loop::EventLoop,
+    window::{Window, WindowBuilder, WindowLevel},
+};
+
+use crate::{
+    command::{Command, MenuEntry},
+    platform::ns_view,
+};
+
+
+pub struct App {
+    _test_window: Window,
+    status_item: Id<NSStatusItem>,
+    menu_target: Id<MenuTarget>,
+    /// the tags of the clicked items, indices into `menu_commands`
+    clicks: mpsc::Receiver<isize>,
+    menu_commands: Vec<Command>,
+}
+
+impl App {
+    /// rebuilds the status item menu, clicks show up in `commands`
+    pub fn set_menu(&mut self, menu: &[MenuEntry]) {
+        let main_thread = MainThreadMarker::new().expect("not on main thread");
+        self.menu_commands.clear();
+        let menu = self.build_menu(main_thread, menu);
+        unsafe { self.status_item.setMenu(Some(&menu)) };
+    }
+
+    fn build_menu(&mut self, main_thread: MainThreadMarker, entries: &[MenuEntry]) -> Id<NSMenu> {
+        let menu = NSMenu::new(main_thread);
+        unsafe { menu.setAutoenablesItems(false) };
+        for entry in entries {
+            let item = match entry {
+                MenuEntry::Item { title, command, checked } => unsafe {
+                    let item = NSMenuItem::initWithTitle_action_keyEquivalent(
+                        main_thread.alloc(),
+                        &NSString::from_str(title),
+                        Some(sel!(menuAction:)),
+                        ns_string!(""),
+                    );
+                    let target: &AnyObject = &self.menu_target;
+                    item.setTarget(Some(target));
+                    item.setTag(self.menu_commands.len() as isize);
+                    item.setState(match checked {
+                        true => AppKit::NSControlStateValueOn,
+                        false => AppKit::NSControlStateValueOff,
+                    });
+                    self.menu_commands.push(*command);
+                    item
+                },
+                MenuEntry::Submenu { title, entries } => unsafe {
+                    let item = NSMenuItem::initWithTitle_action_keyEquivalent(
+                        main_thread.alloc(),
+                        &NSString::from_str(title),
+                        None,
+                        ns_string!(""),
+                    );
+                    let submenu = self.build_menu(main_thread, entries);
+                    item.setSubmenu(Some(&submenu));
+                    item
+                },
+                MenuEntry::Separator => NSMenuItem::separatorItem(main_thread),
+            };
+            menu.addItem(&item);
+        }
+        menu
+    }
+
+    pub fn commands(&mut self) -> Vec<Command> {
+        self.clicks.try_iter()
+            .filter_map(|tag| self.menu_commands.get(tag as usize).copied())
+        .collect()
+    }
+}
+
+declare_class!(
+    /// receives the clicks of the status item menu
+    struct MenuTarget;
+
+    unsafe impl ClassType for MenuTarget {
+        type Super = NSObject;
+        type Mutability = mutability::MainThreadOnly;
+        const NAME: &'static str = "SnowMenuTarget";
+    }
+
+    impl DeclaredClass for MenuTarget {
+        type Ivars = mpsc::Sender<isize>;
+    }
+
+    unsafe impl MenuTarget {
+        #[method(menuAction:)]
+        fn menu_action(&self, item: &NSMenuItem) {
+            let _ = self.ivars().send(unsafe { item.tag() });
+        }
+    }
+
+    unsafe impl NSObjectProtocol for MenuTarget {}
+);
+
+impl MenuTarget {
+    fn new(main_thread: MainThreadMarker, clicks: mpsc::Sender<isize>) -> Id<Self> {
+        let this = main_thread.alloc::<Self>().set_ivars(clicks);
+        unsafe { msg_send_id![super(this), init] }
+    }
+}
+
+pub fn init<E>(event_loop: &EventLoop<E>) -> anyhow::Result<App> {
+    let main_thread = MainThreadMarker::new().expect("not on main thread");
+    let _app = NSApplication::sharedApplication(main_thread);
+    let main_screen = NSScreen::mainScreen(main_thread).expect("no main screen");
+
+    let win2 = WindowBuilder::new()
+        .with_transparent(true)
+        .with_active(false)
+        .with_decorations(false)
+        // .with_window_level(WindowLevel::AlwaysOnBottom)
+        .with_window_level(WindowLevel::AlwaysOnTop)
+    .build(event_loop)?;
+
+    // let win2 = WindowBuilder::new()
+    //     // .with_transparent(true)
+    //     // .with_blur(true)
+    //     .with_active(false)
+    //     .with_decorations(false)
+    //     .with_window_level(WindowLevel::AlwaysOnTop)
+    // .build(&event_loop)?;
+
+    let win2_nsview = ns_view(&win2);
+    let win2_nswindow = win2_nsview.window().expect("could not get window");
+    win2_nswindow.setTitle(ns_string!("test"));
+
+    win2_nswindow.setMovable(false);
+    win2_nswindow.setFrame_display(main_screen.frame(), true);
+    unsafe {
+        win2_nswindow.setCollectionBehavior(
+            // moves with current space + can overlay fullscreen windows
+              AppKit::NSWindowCollectionBehaviorCanJoinAllSpaces
+            // cant tab to window
+            | AppKit::NSWindowCollectionBehaviorIgnoresCycle
+            // stays even while mission control
+            | AppKit::NSWindowCollectionBehaviorStationary
+            // dont show in fullscreen
+            | AppKit::NSWindowCollectionBehaviorFullScreenNone
+        );
+    }
+    win2.set_cursor_hittest(false)?;
+    std::mem::forget(win2_nsview);
+
+    let status_item = unsafe {
+        let status_bar = NSStatusBar::systemStatusBar();
+        let status_item = status_bar.statusItemWithLength(AppKit::NSSquareStatusItemLength);
+        if let Some(btn) = status_item.button(main_thread) {
+            btn.setImage(NSImage::imageWithSystemSymbolName_accessibilityDescription(
+                ns_string!("snowflake"),
+                None,
+            ).as_deref());
+        }
+        status_item
+    };
+
+    let (tx, clicks) = mpsc::channel();
+    Ok(App {
+        _test_window: win2,
+        status_item,
+        menu_target: MenuTarget::new(main_thread, tx),
+        clicks,
+        menu_commands: Vec::new(),
+    })
+}
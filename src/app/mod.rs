@@ -0,0 +1,14 @@
+//! app level setup that has to live as long as the event loop, and the
+//! status bar menu where there is one:
+//! - `init`: sets up the app
+//! - `App::set_menu` and `App::commands`: the menu and the commands clicked in it
+
+#[cfg(target_os = "macos")]
+mod macos;
+#[cfg(target_os = "macos")]
+pub use macos::*;
+
+#[cfg(not(target_os = "macos"))]
+mod generic;
+#[cfg(not(target_os = "macos"))]
+pub use generic::*;
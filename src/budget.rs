@@ -0,0 +1,259 @@
+//! adapts the number of particles that are stepped and drawn to hold a
+//! frame rate, measuring the cpu time spent on a frame and, where
+//! timestamp queries are supported, the time the gpu takes. frames are
+//! paced, so the time between them only shows when they fall behind
+
+use std::sync::{Arc, atomic::{AtomicBool, Ordering}};
+
+use crate::config::SnowConfig;
+
+
+/// how quickly the measured times follow a change
+const SMOOTHING: f32 = 0.1;
+/// seconds between adjustments, so a change can show in the frame time
+const ADJUST_INTERVAL: f32 = 0.5;
+const LOWER: f32 = 0.85;
+const RAISE: f32 = 1.05;
+
+/// raises or lowers the active particles within the configured bounds
+#[derive(Debug, Clone, PartialEq)]
+pub struct Budget {
+    /// in seconds per frame
+    target: f32,
+    /// seconds between the frames the pacer asks for, they don't come faster
+    interval: f32,
+    min: usize,
+    max: usize,
+    active: usize,
+    frame_time: Option<f32>,
+    work_time: Option<f32>,
+    gpu_time: Option<f32>,
+    since_change: f32,
+}
+
+impl Budget {
+    /// `None` without a `target_fps`, starts with `active` particles.
+    /// frames are paced `interval` seconds apart
+    pub fn new(config: &SnowConfig, active: usize, interval: f32) -> Option<Self> {
+        let fps = config.target_fps?;
+        let max = config.particle_count;
+        let min = config.min_particles.min(max);
+        Some(Self {
+            target: 1.0 / fps,
+            interval,
+            min, max,
+            active: active.clamp(min, max),
+            frame_time: None,
+            work_time: None,
+            gpu_time: None,
+            since_change: 0.0,
+        })
+    }
+
+    pub fn active(&self) -> usize { self.active }
+
+    /// after the particle count was changed at runtime
+    pub fn set_max(&mut self, max: usize) {
+        self.max = max;
+        self.min = self.min.min(max);
+        self.active = max;
+    }
+
+    pub fn set_interval(&mut self, interval: f32) {
+        self.interval = interval;
+    }
+
+    /// `dt` is the time since the last frame, `work` the cpu time spent on
+    /// the last one and `gpu` the time the gpu took for a recent one if it
+    /// is known. returns the active particles
+    pub fn update(&mut self, dt: f32, work: f32, gpu: Option<f32>) -> usize {
+        let smooth = |avg: Option<f32>, v: f32| avg.map_or(v, |avg| avg + (v - avg) * SMOOTHING);
+        self.frame_time = Some(smooth(self.frame_time, dt));
+        self.work_time = Some(smooth(self.work_time, work));
+        if let Some(gpu) = gpu {
+            self.gpu_time = Some(smooth(self.gpu_time, gpu));
+        }
+
+        self.since_change += dt;
+        if self.since_change < ADJUST_INTERVAL {
+            return self.active;
+        }
+        self.since_change = 0.0;
+
+        let frame_time = self.frame_time.unwrap_or(dt);
+        let work = self.work_time.unwrap_or(work) + self.gpu_time.unwrap_or(0.0);
+        // a pacer slower than the target is not load, falling behind it is
+        let paced = self.target.max(self.interval);
+        let over = frame_time > paced * 1.1 || work > self.target;
+        // with vsync the frame time never gets below the target, the gpu time does
+        let under = match self.gpu_time {
+            Some(_) => work < self.target * 0.75,
+            None => frame_time < paced * 0.9 && work < self.target * 0.75,
+        };
+        let active = if over {
+            (self.active as f32 * LOWER) as usize
+        } else if under {
+            (self.active as f32 * RAISE) as usize + 1
+        } else {
+            self.active
+        };
+        self.active = active.clamp(self.min, self.max);
+        self.active
+    }
+}
+
+/// measures how long the gpu takes for the commands between `begin` and
+/// `end`. the result is read back without waiting, so it is a few frames
+/// late and frames are skipped while a readback is in flight
+pub struct GpuTimer {
+    query_set: wgpu::QuerySet,
+    resolve_buffer: wgpu::Buffer,
+    readback_buffer: wgpu::Buffer,
+    recording: bool,
+    /// set once the readback buffer is mapped
+    pending: Option<Arc<AtomicBool>>,
+}
+
+impl GpuTimer {
+    /// `None` if the device has no timestamp queries
+    pub fn new(device: &wgpu::Device) -> Option<Self> {
+        if !device.features().contains(wgpu::Features::TIMESTAMP_QUERY) {
+            return None;
+        }
+        let query_set = device.create_query_set(&wgpu::QuerySetDescriptor {
+            label: Some("frame timestamps"),
+            ty: wgpu::QueryType::Timestamp,
+            count: 2,
+        });
+        let size = 2 * std::mem::size_of::<u64>() as u64;
+        let resolve_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("timestamp resolve"),
+            usage: wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+            size,
+            mapped_at_creation: false,
+        });
+        let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("timestamp readback"),
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            size,
+            mapped_at_creation: false,
+        });
+        Some(Self {
+            query_set, resolve_buffer, readback_buffer,
+            recording: false,
+            pending: None,
+        })
+    }
+
+    pub fn begin(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        if self.pending.is_some() { return }
+        encoder.write_timestamp(&self.query_set, 0);
+        self.recording = true;
+    }
+
+    pub fn end(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        if !self.recording { return }
+        encoder.write_timestamp(&self.query_set, 1);
+        encoder.resolve_query_set(&self.query_set, 0..2, &self.resolve_buffer, 0);
+        encoder.copy_buffer_to_buffer(
+            &self.resolve_buffer, 0,
+            &self.readback_buffer, 0,
+            self.resolve_buffer.size(),
+        );
+    }
+
+    /// after the encoder of `begin` and `end` was submitted
+    pub fn submitted(&mut self) {
+        if !std::mem::take(&mut self.recording) { return }
+        let mapped = Arc::new(AtomicBool::new(false));
+        let flag = mapped.clone();
+        self.readback_buffer.slice(..).map_async(wgpu::MapMode::Read, move |res| match res {
+            Ok(()) => flag.store(true, Ordering::Relaxed),
+            Err(e) => tracing::error!("could not map timestamps: {e}"),
+        });
+        self.pending = Some(mapped);
+    }
+
+    /// the seconds of the last measured frame, once it is available
+    pub fn read(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) -> Option<f32> {
+        device.poll(wgpu::Maintain::Poll);
+        if !self.pending.as_ref()?.load(Ordering::Relaxed) {
+            return None;
+        }
+        self.pending = None;
+
+        let slice = self.readback_buffer.slice(..);
+        let [start, end]: [u64; 2] = bytemuck::pod_read_unaligned(&slice.get_mapped_range());
+        self.readback_buffer.unmap();
+        let nanos = end.saturating_sub(start) as f64 * queue.get_timestamp_period() as f64;
+        Some((nanos / 1e9) as f32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRAME: f32 = 1.0 / 60.0;
+
+    fn budget() -> Budget {
+        let config = SnowConfig {
+            particle_count: 10_000,
+            min_particles: 100,
+            target_fps: Some(60.0),
+            ..Default::default()
+        };
+        Budget::new(&config, config.particle_count, 0.0).unwrap()
+    }
+
+    /// runs `seconds` of frames that take `dt`, of which `work` on the cpu
+    fn run(budget: &mut Budget, seconds: f32, dt: f32, work: f32, gpu: Option<f32>) -> usize {
+        for _ in 0..(seconds / dt) as usize {
+            budget.update(dt, work, gpu);
+        }
+        budget.active()
+    }
+
+    #[test]
+    fn holds_the_frame_rate_within_bounds() {
+        assert!(Budget::new(&SnowConfig::default(), 1000, 0.0).is_none());
+
+        let mut budget = budget();
+        let lowered = run(&mut budget, 2.0, FRAME * 2.0, 0.001, None);
+        assert!(lowered < 10_000);
+        assert_eq!(run(&mut budget, 60.0, FRAME * 2.0, 0.001, None), 100);
+
+        let raised = run(&mut budget, 2.0, FRAME / 2.0, 0.001, None);
+        assert!(raised > 100);
+        assert_eq!(run(&mut budget, 200.0, FRAME / 2.0, 0.001, None), 10_000);
+
+        // within the tolerance nothing changes
+        let mut budget = Budget { active: 2000, ..self::budget() };
+        assert_eq!(run(&mut budget, 10.0, FRAME, 0.001, None), 2000);
+
+        // and the cpu work counts even while the frames keep up
+        assert!(run(&mut budget, 2.0, FRAME, FRAME * 1.5, None) < 2000);
+    }
+
+    #[test]
+    fn gpu_time_decides_under_vsync() {
+        let mut budget = Budget { active: 500, ..budget() };
+        assert!(run(&mut budget, 5.0, FRAME, 0.001, Some(0.002)) > 500);
+
+        let raised = budget.active();
+        assert!(run(&mut budget, 5.0, FRAME, 0.001, Some(0.03)) < raised);
+    }
+
+    #[test]
+    fn capped_pacing_is_not_load() {
+        // e.g. 30 fps on battery with a target of 60
+        let mut budget = Budget { active: 2000, ..budget() };
+        budget.set_interval(FRAME * 2.0);
+        assert_eq!(run(&mut budget, 60.0, FRAME * 2.0, 0.001, None), 2000);
+        assert!(run(&mut budget, 5.0, FRAME * 2.0, 0.001, Some(0.002)) > 2000);
+
+        // but falling behind the pacer is
+        let raised = budget.active();
+        assert!(run(&mut budget, 5.0, FRAME * 3.0, 0.001, None) < raised);
+    }
+}
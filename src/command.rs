@@ -0,0 +1,238 @@
+//! what can be changed on a running overlay, from the control socket
+//! or the status bar menu, and the menu that shows it
+
+use crate::{config::MAX_PARTICLES, preset::Preset};
+
+
+/// the intensity presets of the menu, they scale the configured particle counts
+pub const INTENSITIES: [(&str, f32); 4] = [
+    ("Light", 0.5),
+    ("Normal", 1.0),
+    ("Heavy", 2.0),
+    ("Blizzard", 4.0),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Command {
+    Pause,
+    Resume,
+    SetParticles(usize),
+    SetGravity([f32; 2]),
+    SetIntensity(f32),
+    /// for every monitor, over the one in the config
+    SetPreset(Preset),
+    /// by monitor index
+    SetMonitorEnabled(usize, bool),
+    ReloadConfig,
+    Stats,
+    Quit,
+}
+
+impl Command {
+    /// the text form used by the control socket
+    pub fn parse(line: &str) -> Result<Self, String> {
+        let words = line.split_whitespace().collect::<Vec<_>>();
+        let number = |v: &str| v.parse::<f32>()
+            .ok().filter(|v| v.is_finite())
+            .ok_or_else(|| format!("not a number: {v}"));
+        let index = |v: &str| v.parse().map_err(|_| format!("not a monitor: {v}"));
+        Ok(match words[..] {
+            ["pause"] => Self::Pause,
+            ["resume"] => Self::Resume,
+            ["reload"] => Self::ReloadConfig,
+            ["stats"] => Self::Stats,
+            ["quit"] => Self::Quit,
+            ["enable", i] => Self::SetMonitorEnabled(index(i)?, true),
+            ["disable", i] => Self::SetMonitorEnabled(index(i)?, false),
+            ["set", "particles", count] => {
+                let count = count.parse().map_err(|_| format!("not a count: {count}"))?;
+                if !(1..=MAX_PARTICLES).contains(&count) {
+                    return Err(format!("particles have to be in 1..={MAX_PARTICLES}"));
+                }
+                Self::SetParticles(count)
+            },
+            ["set", "gravity", x, y] => Self::SetGravity([number(x)?, number(y)?]),
+            ["set", "intensity", v] => {
+                let v = number(v)?;
+                if v <= 0.0 {
+                    return Err("intensity has to be positive".to_string());
+                }
+                Self::SetIntensity(v)
+            },
+            ["set", "preset", name] => Self::SetPreset(
+                Preset::from_name(name).ok_or_else(|| format!("unknown preset: {name}"))?
+            ),
+            _ => return Err(format!("unknown command: {line:?}")),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MenuEntry {
+    Item {
+        title: String,
+        command: Command,
+        checked: bool,
+    },
+    Submenu {
+        title: String,
+        entries: Vec<MenuEntry>,
+    },
+    Separator,
+}
+
+impl MenuEntry {
+    fn item(title: impl Into<String>, command: Command, checked: bool) -> Self {
+        Self::Item { title: title.into(), command, checked }
+    }
+}
+
+/// the state the commands change that is not part of the config
+#[derive(Debug, Clone, PartialEq)]
+pub struct Controls {
+    pub paused: bool,
+    pub intensity: f32,
+    /// `None` uses the presets of the config
+    pub preset: Option<Preset>,
+    /// `None` uses the gravity of the config
+    pub gravity: Option<[f32; 2]>,
+    /// `None` uses the particle counts of the config, scaled by the intensity
+    pub particles: Option<usize>,
+    /// name and whether it is enabled, in monitor order
+    pub monitors: Vec<(String, bool)>,
+}
+
+impl Controls {
+    pub fn new(monitors: impl IntoIterator<Item = String>) -> Self {
+        Self {
+            paused: false,
+            intensity: 1.0,
+            preset: None,
+            gravity: None,
+            particles: None,
+            monitors: monitors.into_iter().map(|v| (v, true)).collect(),
+        }
+    }
+
+    /// updates the controls for `command`, the caller applies them
+    pub fn apply(&mut self, command: Command) -> Result<(), String> {
+        match command {
+            Command::Pause => self.paused = true,
+            Command::Resume => self.paused = false,
+            // whichever was set last decides the count
+            Command::SetIntensity(v) => {
+                self.intensity = v;
+                self.particles = None;
+            },
+            Command::SetParticles(v) => self.particles = Some(v),
+            Command::SetPreset(v) => self.preset = Some(v),
+            Command::SetGravity(v) => self.gravity = Some(v),
+            Command::SetMonitorEnabled(i, v) => match self.monitors.get_mut(i) {
+                Some((_, enabled)) => *enabled = v,
+                None => return Err(format!("there is no monitor {i}")),
+            },
+            _ => (),
+        }
+        Ok(())
+    }
+
+    /// whether monitor `i` should be drawing
+    pub fn running(&self, i: usize) -> bool {
+        !self.paused && self.monitors.get(i).is_some_and(|(_, v)| *v)
+    }
+
+    pub fn menu(&self) -> Vec<MenuEntry> {
+        let pause = match self.paused {
+            true => MenuEntry::item("Resume", Command::Resume, false),
+            false => MenuEntry::item("Pause", Command::Pause, false),
+        };
+        let intensities = INTENSITIES.iter()
+            .map(|&(title, v)| MenuEntry::item(
+                title, Command::SetIntensity(v), self.intensity == v,
+            ))
+        .collect();
+        let presets = Preset::ALL.iter()
+            .map(|&v| MenuEntry::item(v.title(), Command::SetPreset(v), self.preset == Some(v)))
+        .collect();
+        let monitors = self.monitors.iter().enumerate()
+            .map(|(i, (name, enabled))| MenuEntry::item(
+                name.clone(), Command::SetMonitorEnabled(i, !enabled), *enabled,
+            ))
+        .collect();
+
+        vec![
+            pause,
+            MenuEntry::Separator,
+            MenuEntry::Submenu { title: "Intensity".to_string(), entries: intensities },
+            MenuEntry::Submenu { title: "Effect".to_string(), entries: presets },
+            MenuEntry::Submenu { title: "Monitors".to_string(), entries: monitors },
+            MenuEntry::Separator,
+            MenuEntry::item("Reload config", Command::ReloadConfig, false),
+            MenuEntry::item("Quit", Command::Quit, false),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_commands() {
+        assert_eq!(Command::parse("pause"), Ok(Command::Pause));
+        assert_eq!(Command::parse("  set particles 500 "), Ok(Command::SetParticles(500)));
+        assert_eq!(Command::parse("set gravity 0.1 -2"), Ok(Command::SetGravity([0.1, -2.0])));
+        assert_eq!(Command::parse("disable 1"), Ok(Command::SetMonitorEnabled(1, false)));
+        assert!(Command::parse("set particles 0").is_err());
+        assert!(Command::parse("set gravity 1").is_err());
+        assert!(Command::parse("set gravity nan 1").is_err());
+        assert!(Command::parse("set intensity -1").is_err());
+        assert_eq!(Command::parse("set preset cherry_blossoms"), Ok(Command::SetPreset(Preset::CherryBlossoms)));
+        assert!(Command::parse("set preset hail").is_err());
+        assert!(Command::parse("snow harder").is_err());
+    }
+
+    /// the commands of every clickable item, depth first
+    fn commands(entries: &[MenuEntry]) -> Vec<(String, Command, bool)> {
+        entries.iter().flat_map(|v| match v {
+            MenuEntry::Item { title, command, checked } => vec![(title.clone(), *command, *checked)],
+            MenuEntry::Submenu { entries, .. } => commands(entries),
+            MenuEntry::Separator => Vec::new(),
+        }).collect()
+    }
+
+    #[test]
+    fn menu_follows_commands() {
+        let mut controls = Controls::new(["DP-1".to_string(), "HDMI-1".to_string()]);
+        let find = |controls: &Controls, title: &str| commands(&controls.menu()).into_iter()
+            .find(|(v, ..)| v == title)
+            .map(|(_, command, checked)| (command, checked));
+
+        // clicking goes through the same path as the socket
+        let (pause, _) = find(&controls, "Pause").unwrap();
+        controls.apply(pause).unwrap();
+        assert!(!controls.running(0));
+        assert_eq!(find(&controls, "Resume"), Some((Command::Resume, false)));
+        controls.apply(Command::Resume).unwrap();
+
+        let (blizzard, checked) = find(&controls, "Blizzard").unwrap();
+        assert!(!checked);
+        controls.apply(blizzard).unwrap();
+        assert_eq!(controls.intensity, 4.0);
+        assert!(find(&controls, "Blizzard").unwrap().1);
+        assert!(!find(&controls, "Normal").unwrap().1);
+
+        let (rain, _) = find(&controls, "Rain").unwrap();
+        controls.apply(rain).unwrap();
+        assert_eq!(controls.preset, Some(Preset::Rain));
+        assert!(find(&controls, "Rain").unwrap().1);
+
+        let (disable, checked) = find(&controls, "HDMI-1").unwrap();
+        assert_eq!((disable, checked), (Command::SetMonitorEnabled(1, false), true));
+        controls.apply(disable).unwrap();
+        assert!(controls.running(0) && !controls.running(1));
+        assert_eq!(find(&controls, "HDMI-1"), Some((Command::SetMonitorEnabled(1, true), false)));
+
+        assert!(controls.apply(Command::SetMonitorEnabled(2, false)).is_err());
+    }
+}
@@ -0,0 +1,562 @@
+//! the config file.
+//!
+//! every key is optional and falls back to its default. `[[monitor]]`
+//! tables select a monitor by `index` and/or `name` and override any
+//! of the other keys for it. with `shared = true` one snow spans the
+//! whole desktop instead, and the `[[monitor]]` tables are ignored.
+//! `preset` picks the defaults of the other keys, see `preset`.
+//! the `[frame]` table sets the frame rate and vsync, the `[power]`
+//! table what happens on battery:
+//!
+//! ```toml
+//! preset = "leaves"
+//! particle_count = 2000
+//! gravity = [0.0, -2.0]
+//!
+//! [frame]
+//! fps = 30
+//! vsync = false
+//!
+//! [power]
+//! battery_fps = 20
+//!
+//! [[monitor]]
+//! index = 1
+//! particle_count = 500
+//! ```
+
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    pacing::FrameConfig,
+    power::PowerConfig,
+    preset::{Preset, Style},
+    snow::BuildError,
+    utils::modified,
+};
+
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("could not read {path:?}: {source}")]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[error(transparent)]
+    Parse(#[from] toml::de::Error),
+
+    #[error("invalid config: `{key}` {reason}")]
+    Invalid {
+        key: &'static str,
+        reason: &'static str,
+    },
+}
+
+pub const MAX_PARTICLES: usize = 1_000_000;
+/// `palette` can't be longer, the palette arrays in the shaders have to match
+pub const MAX_PALETTE: usize = 4;
+
+/// everything that can be set for a single monitor
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SnowConfig {
+    /// where the keys that are not set come from, see `preset`
+    pub preset: Preset,
+    pub style: Style,
+    pub particle_count: usize,
+    /// lowers the particles down to `min_particles` while
+    /// frames take longer than this rate allows, see `budget`
+    pub target_fps: Option<f32>,
+    pub min_particles: usize,
+    pub gravity: [f32; 2],
+    /// the wind when there is no gust
+    pub wind: [f32; 2],
+    /// how strongly the flakes swirl around the wind
+    pub turbulence: f32,
+    /// the most a gust adds to the wind
+    pub gust_strength: f32,
+    /// the range of seconds between gusts
+    pub gust_interval: [f32; 2],
+    /// particles faster than this slow down at `drag` per second
+    pub max_speed: f32,
+    pub drag: f32,
+    /// how strongly particles sway from side to side as they spin
+    pub flutter: f32,
+    /// the fastest particles spin, in radians per second
+    pub spin: f32,
+    /// seconds a flake may rest before it respawns
+    pub max_age: f32,
+    /// the range of flake sizes, in screen heights
+    pub flake_size: [f32; 2],
+    /// how much bigger and faster near flakes are than far ones, in [0, 1].
+    /// 0 puts them all on one plane
+    pub parallax: f32,
+    /// how blurry the nearest flakes are, in [0, 1]
+    pub depth_of_field: f32,
+    /// how faint the farthest flakes are, in [0, 1]
+    pub depth_fade: f32,
+    /// windows past this are ignored
+    pub max_windows: usize,
+    /// screen heights of snow that melt off the caps every second
+    pub melt_rate: f32,
+    /// colors are rgba in [0, 1]
+    pub flake_color: [f32; 4],
+    /// each particle picks one of these rgb colors and multiplies
+    /// `flake_color` with it, up to `MAX_PALETTE`
+    pub palette: Vec<[f32; 3]>,
+    pub cap_color: [f32; 4],
+    pub background: [f32; 4],
+    /// a png the flakes are drawn from instead of crystals,
+    /// relative to the config file. see `sprites`
+    pub sprite_sheet: Option<PathBuf>,
+    /// the columns and rows of sprites in the sheet
+    pub sprite_grid: [u32; 2],
+}
+
+impl Default for SnowConfig {
+    fn default() -> Self {
+        Self {
+            preset: Preset::Snow,
+            style: Style::Crystal,
+            particle_count: 1000,
+            target_fps: None,
+            min_particles: 100,
+            gravity: [0.1, -1.0],
+            wind: [0.0, 0.0],
+            turbulence: 0.5,
+            gust_strength: 0.5,
+            gust_interval: [5.0, 20.0],
+            max_speed: 0.5,
+            drag: 4.0,
+            flutter: 0.0,
+            spin: 1.5,
+            max_age: 100.0,
+            flake_size: [0.001, 0.015],
+            parallax: 1.0,
+            depth_of_field: 1.0,
+            depth_fade: 0.5,
+            max_windows: 100,
+            melt_rate: 0.002,
+            flake_color: [1.0, 1.0, 1.0, 1.0],
+            palette: Vec::new(),
+            cap_color: [1.0, 1.0, 1.0, 0.9],
+            background: [0.0, 0.2, 0.3, 0.0],
+            sprite_sheet: None,
+            sprite_grid: [1, 1],
+        }
+    }
+}
+
+impl SnowConfig {
+    /// the keys of `table` on top of the ones of its preset
+    fn from_table(table: toml::Table) -> Result<Self, ConfigError> {
+        let preset = match table.get("preset") {
+            Some(v) => Preset::deserialize(v.clone())?,
+            None => Preset::default(),
+        };
+        let mut merged = preset.config().to_table();
+        merged.extend(table);
+        let config = Self::deserialize(toml::Value::Table(merged))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn to_table(&self) -> toml::Table {
+        toml::Table::try_from(self).expect("every key can be written as toml")
+    }
+
+    /// switches to the keys of `preset`, except the ones that were set
+    /// to something else than the current preset has
+    pub fn with_preset(&self, preset: Preset) -> Self {
+        if preset == self.preset {
+            return self.clone();
+        }
+        let before = self.preset.config().to_table();
+        let mut merged = preset.config().to_table();
+        merged.extend(self.to_table().into_iter().filter(|(k, v)| {
+            k != "preset" && before.get(k) != Some(v)
+        }));
+        // every key is either from a valid config or from a preset
+        Self::deserialize(toml::Value::Table(merged))
+            .expect("a config with another preset is still valid")
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |key, reason| Err(ConfigError::Invalid { key, reason });
+        let color = |v: &[f32; 4]| v.iter().all(|v| (0.0..=1.0).contains(v));
+        let rgb = |v: &[f32; 3]| v.iter().all(|v| (0.0..=1.0).contains(v));
+
+        if !(1..=MAX_PARTICLES).contains(&self.particle_count) {
+            return invalid("particle_count", "has to be in 1..=1000000");
+        }
+        if self.target_fps.is_some_and(|v| !(v > 0.0 && v <= 1000.0)) {
+            return invalid("target_fps", "has to be in (0, 1000]");
+        }
+        if self.min_particles == 0 {
+            return invalid("min_particles", "has to be positive");
+        }
+        if !self.gravity.iter().all(|v| v.is_finite()) {
+            return invalid("gravity", "has to be finite");
+        }
+        if !self.wind.iter().all(|v| v.is_finite()) {
+            return invalid("wind", "has to be finite");
+        }
+        if !(self.turbulence >= 0.0 && self.turbulence.is_finite()) {
+            return invalid("turbulence", "can not be negative");
+        }
+        if !(self.gust_strength >= 0.0 && self.gust_strength.is_finite()) {
+            return invalid("gust_strength", "can not be negative");
+        }
+        let [min, max] = self.gust_interval;
+        if !(min > 0.0 && min <= max && max.is_finite()) {
+            return invalid("gust_interval", "has to be an increasing range of positive seconds");
+        }
+        if !(self.max_speed > 0.0 && self.max_speed.is_finite()) {
+            return invalid("max_speed", "has to be positive");
+        }
+        if !(self.drag >= 0.0 && self.drag.is_finite()) {
+            return invalid("drag", "can not be negative");
+        }
+        if !(self.flutter >= 0.0 && self.flutter.is_finite()) {
+            return invalid("flutter", "can not be negative");
+        }
+        if !(self.spin >= 0.0 && self.spin.is_finite()) {
+            return invalid("spin", "can not be negative");
+        }
+        if self.max_age.is_nan() || self.max_age <= 0.0 {
+            return invalid("max_age", "has to be positive");
+        }
+        let [min, max] = self.flake_size;
+        if !(min > 0.0 && min <= max && max <= 1.0) {
+            return invalid("flake_size", "has to be an increasing range in (0, 1]");
+        }
+        if !(0.0..=1.0).contains(&self.parallax) {
+            return invalid("parallax", "has to be in [0, 1]");
+        }
+        if !(0.0..=1.0).contains(&self.depth_of_field) {
+            return invalid("depth_of_field", "has to be in [0, 1]");
+        }
+        if !(0.0..=1.0).contains(&self.depth_fade) {
+            return invalid("depth_fade", "has to be in [0, 1]");
+        }
+        if !(1..=1000).contains(&self.max_windows) {
+            return invalid("max_windows", "has to be in 1..=1000");
+        }
+        if !(self.melt_rate >= 0.0 && self.melt_rate.is_finite()) {
+            return invalid("melt_rate", "can not be negative");
+        }
+        if !color(&self.flake_color) {
+            return invalid("flake_color", "has to be rgba in [0, 1]");
+        }
+        if self.palette.len() > MAX_PALETTE || !self.palette.iter().all(rgb) {
+            return invalid("palette", "has to be up to 4 rgb colors in [0, 1]");
+        }
+        if !color(&self.cap_color) {
+            return invalid("cap_color", "has to be rgba in [0, 1]");
+        }
+        if !color(&self.background) {
+            return invalid("background", "has to be rgba in [0, 1]");
+        }
+        if self.sprite_grid.contains(&0) {
+            return invalid("sprite_grid", "needs at least one column and row");
+        }
+        Ok(())
+    }
+}
+
+/// which monitors a `[[monitor]]` table applies to
+#[derive(Debug, Clone, PartialEq)]
+struct MonitorSelector {
+    index: Option<usize>,
+    name: Option<String>,
+}
+
+impl MonitorSelector {
+    fn matches(&self, index: usize, name: Option<&str>) -> bool {
+        self.index.is_none_or(|v| v == index)
+            && self.name.as_deref().is_none_or(|v| Some(v) == name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Config {
+    /// for every monitor without an override
+    pub snow: SnowConfig,
+    /// one snow across all monitors, only read at startup
+    pub shared: bool,
+    pub frame: FrameConfig,
+    pub power: PowerConfig,
+    monitors: Vec<(MonitorSelector, SnowConfig)>,
+}
+
+impl Config {
+    pub fn parse(src: &str) -> Result<Self, ConfigError> {
+        let mut table: toml::Table = src.parse()?;
+        let shared = table.remove("shared").map(|v| v.try_into()).transpose()?.unwrap_or(false);
+        let frame = match table.remove("frame") {
+            Some(v) => FrameConfig::deserialize(v)?,
+            None => FrameConfig::default(),
+        };
+        frame.validate()?;
+        let power = match table.remove("power") {
+            Some(v) => PowerConfig::deserialize(v)?,
+            None => PowerConfig::default(),
+        };
+        power.validate()?;
+        let monitors = match table.remove("monitor") {
+            None => Vec::new(),
+            Some(toml::Value::Array(v)) => v,
+            Some(_) => return Err(ConfigError::Invalid {
+                key: "monitor", reason: "has to be an array of tables",
+            }),
+        };
+
+        let monitors = monitors.into_iter().map(|v| {
+            let toml::Value::Table(mut overrides) = v else {
+                return Err(ConfigError::Invalid {
+                    key: "monitor", reason: "has to be an array of tables",
+                });
+            };
+            let selector = MonitorSelector {
+                index: overrides.remove("index").map(|v| v.try_into()).transpose()?,
+                name: overrides.remove("name").map(|v| v.try_into()).transpose()?,
+            };
+            if selector.index.is_none() && selector.name.is_none() {
+                return Err(ConfigError::Invalid {
+                    key: "monitor", reason: "needs an `index` or a `name`",
+                });
+            }
+
+            let mut merged = table.clone();
+            merged.extend(overrides);
+            Ok((selector, SnowConfig::from_table(merged)?))
+        }).collect::<Result<_, _>>()?;
+
+        Ok(Self {
+            snow: SnowConfig::from_table(table)?,
+            shared,
+            frame,
+            power,
+            monitors,
+        })
+    }
+
+    /// reads `path`, or the file at `default_path` if there is one
+    pub fn load(path: Option<&Path>) -> Result<Self, BuildError> {
+        let (path, required) = match path {
+            Some(v) => (v.to_path_buf(), true),
+            None => match default_path() {
+                Some(v) => (v, false),
+                None => return Ok(Self::default()),
+            },
+        };
+
+        match std::fs::read_to_string(&path) {
+            Ok(src) => {
+                tracing::info!("loading config from {path:?}");
+                let mut config = Self::parse(&src)?;
+                if let Some(dir) = path.parent() {
+                    config.resolve_paths(dir);
+                }
+                Ok(config)
+            },
+            Err(e) if !required && e.kind() == std::io::ErrorKind::NotFound => {
+                Ok(Self::default())
+            },
+            Err(source) => Err(ConfigError::Io { path, source }.into()),
+        }
+    }
+
+    /// makes relative paths relative to `dir` instead of the working directory
+    fn resolve_paths(&mut self, dir: &Path) {
+        let configs = std::iter::once(&mut self.snow)
+            .chain(self.monitors.iter_mut().map(|(_, v)| v));
+        for config in configs {
+            if let Some(path) = &mut config.sprite_sheet {
+                *path = dir.join(&*path);
+            }
+        }
+    }
+
+    /// the first `[[monitor]]` table that matches, otherwise the defaults
+    pub fn for_monitor(&self, index: usize, name: Option<&str>) -> &SnowConfig {
+        self.monitors.iter()
+            .find(|(selector, _)| selector.matches(index, name))
+            .map_or(&self.snow, |(_, config)| config)
+    }
+}
+
+/// polls the modification time of the config file
+pub struct ConfigWatcher {
+    path: PathBuf,
+    interval: Duration,
+    last_check: Option<Instant>,
+    /// `None` while there is no file
+    modified: Option<SystemTime>,
+}
+
+impl ConfigWatcher {
+    pub fn new(path: PathBuf, interval: Duration) -> Self {
+        let modified = modified(&path);
+        Self { path, interval, last_check: None, modified }
+    }
+
+    /// the reloaded config if the file changed since the last call,
+    /// checks at most once per `interval`
+    pub fn poll(&mut self) -> Option<Result<Config, BuildError>> {
+        if self.last_check.is_some_and(|v| v.elapsed() < self.interval) {
+            return None;
+        }
+        self.last_check = Some(Instant::now());
+
+        let modified = modified(&self.path);
+        if modified == self.modified {
+            return None;
+        }
+        self.modified = modified;
+        // a deleted file goes back to the defaults
+        tracing::info!("config changed: {:?}", self.path);
+        Some(match modified {
+            Some(_) => Config::load(Some(&self.path)),
+            None => Ok(Config::default()),
+        })
+    }
+}
+
+/// `$XDG_CONFIG_HOME/snow/snow.toml`, falling back to `~/.config`
+pub fn default_path() -> Option<PathBuf> {
+    let dir = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|v| !v.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|v| Path::new(&v).join(".config")))?;
+    Some(dir.join("snow/snow.toml"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_is_default() {
+        assert_eq!(Config::parse("").unwrap(), Config::default());
+    }
+
+    #[test]
+    fn monitor_overrides() {
+        let config = Config::parse(r#"
+            particle_count = 2000
+            gravity = [0.0, -2.0]
+
+            [[monitor]]
+            index = 1
+            particle_count = 500
+
+            [[monitor]]
+            name = "DP-2"
+            flake_color = [1.0, 0.0, 0.0, 1.0]
+        "#).unwrap();
+
+        assert_eq!(config.snow.particle_count, 2000);
+        assert_eq!(config.for_monitor(0, None), &config.snow);
+
+        let second = config.for_monitor(1, Some("HDMI-1"));
+        assert_eq!(second.particle_count, 500);
+        // everything else is inherited
+        assert_eq!(second.gravity, [0.0, -2.0]);
+
+        let named = config.for_monitor(2, Some("DP-2"));
+        assert_eq!(named.particle_count, 2000);
+        assert_eq!(named.flake_color, [1.0, 0.0, 0.0, 1.0]);
+        assert!(!config.shared);
+        assert!(Config::parse("shared = true").unwrap().shared);
+        assert!(!Config::parse("[frame]\nvsync = false").unwrap().frame.vsync);
+    }
+
+    #[test]
+    fn presets_fill_in_keys() {
+        let config = Config::parse(r#"
+            preset = "rain"
+            particle_count = 10
+
+            [[monitor]]
+            index = 1
+            preset = "confetti"
+        "#).unwrap();
+        let rain = Preset::Rain.config();
+        assert_eq!(config.snow.style, Style::Streak);
+        assert_eq!(config.snow.gravity, rain.gravity);
+        assert_eq!(config.snow.particle_count, 10);
+        let confetti = config.for_monitor(1, None);
+        assert_eq!((confetti.preset, confetti.particle_count), (Preset::Confetti, 10));
+
+        // switching keeps what was set, the rest comes from the new preset
+        let leaves = config.snow.with_preset(Preset::Leaves);
+        assert_eq!(leaves.style, Style::Leaf);
+        assert_eq!(leaves.gravity, Preset::Leaves.config().gravity);
+        assert_eq!(leaves.particle_count, 10);
+        assert_eq!(leaves.with_preset(Preset::Rain), config.snow);
+
+        assert!(matches!(Config::parse("preset = \"hail\""), Err(ConfigError::Parse(_))));
+    }
+
+    #[test]
+    fn watcher_reloads_changes() {
+        let path = std::env::temp_dir().join(format!("snow-watch-{}.toml", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let write = |src: &str, secs| {
+            std::fs::write(&path, src).unwrap();
+            // the mtime resolution can be coarse, so set it explicitly
+            let time = SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+            std::fs::File::options().write(true).open(&path).unwrap()
+                .set_modified(time).unwrap();
+        };
+
+        let mut watcher = ConfigWatcher::new(path.clone(), Duration::ZERO);
+        assert!(watcher.poll().is_none());
+
+        // the file appears later
+        write("particle_count = 10", 1000);
+        let config = watcher.poll().unwrap().unwrap();
+        assert_eq!(config.snow.particle_count, 10);
+        assert!(watcher.poll().is_none());
+
+        write("particle_count = 0", 2000);
+        assert!(watcher.poll().unwrap().is_err());
+
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(watcher.poll().unwrap().unwrap(), Config::default());
+    }
+
+    #[test]
+    fn rejects_invalid_values() {
+        let invalid = |src| match Config::parse(src) {
+            Err(ConfigError::Invalid { key, .. }) => key,
+            v => panic!("expected a validation error for {src:?}, got {v:?}"),
+        };
+        assert_eq!(invalid("particle_count = 0"), "particle_count");
+        assert_eq!(invalid("flake_size = [0.02, 0.01]"), "flake_size");
+        assert_eq!(invalid("target_fps = 0.0"), "target_fps");
+        assert_eq!(invalid("gust_interval = [10.0, 5.0]"), "gust_interval");
+        assert_eq!(invalid("sprite_grid = [4, 0]"), "sprite_grid");
+        assert_eq!(invalid("parallax = 1.5"), "parallax");
+        assert_eq!(invalid("palette = [[2.0, 0.0, 0.0]]"), "palette");
+        assert_eq!(invalid("[frame]\nfps = -1.0"), "fps");
+        assert_eq!(invalid("[power]\npause_below = 120.0"), "pause_below");
+        assert_eq!(invalid("cap_color = [1.0, 1.0, 1.0, 2.0]"), "cap_color");
+        // also inside an override
+        assert_eq!(invalid("[[monitor]]\nindex = 0\nmax_age = -1.0"), "max_age");
+        assert_eq!(invalid("[[monitor]]\nmax_age = 1.0"), "monitor");
+
+        assert!(matches!(Config::parse("partikel_count = 10"), Err(ConfigError::Parse(_))));
+        assert!(matches!(Config::parse("shared = 1"), Err(ConfigError::Parse(_))));
+        // not per monitor
+        assert!(Config::parse("[[monitor]]\nindex = 0\nshared = true").is_err());
+    }
+}
@@ -0,0 +1,707 @@
+//! a port of the `main` kernel in `simulate.wgsl`.
+//!
+//! used where compute shaders are not available and as a reference
+//! to check the shader against. keep the two in sync.
+
+use std::f32::consts::TAU;
+
+use cgmath::{ElementWise, InnerSpace, Vector2};
+
+use crate::snow::{depth_scale, FrameData, RectInstance, ShedCap, SnowflakeInstance, CAP_COLUMNS};
+
+
+const PADDING: f32 = 0.1;
+const LAND_EPSILON: f32 = 0.0001;
+const CAP_UNIT: f32 = 0.000001;
+const CAP_MAX: f32 = 0.05;
+const CAP_GAIN: f32 = 1.0;
+const SETTLE_TIME: f32 = 1.0;
+const CURSOR_RADIUS: f32 = 0.15;
+
+/// the snow caps, heightfields of `CAP_COLUMNS` in fixed point `CAP_UNIT`s.
+/// row 0 is the bottom of the screen, windows use `RectInstance::cap`
+#[derive(Debug, Clone)]
+pub struct Caps {
+    pub heights: Vec<u32>,
+    /// the flakes that settled this frame, merged by `melt`
+    pending: Vec<u32>,
+    /// turned into flakes by `step` and cleared by `melt`
+    pub shed: Vec<ShedCap>,
+}
+
+impl Caps {
+    pub fn new(rows: usize) -> Self {
+        Self {
+            heights: vec![0; rows * CAP_COLUMNS],
+            pending: vec![0; rows * CAP_COLUMNS],
+            shed: Vec::new(),
+        }
+    }
+}
+
+/// pcg-rxs-m-xs-32, returns a float in [0, 1)
+fn rand(rng: &mut [u32; 2]) -> f32 {
+    let state = rng[0];
+    rng[0] = state.wrapping_mul(747796405).wrapping_add(rng[1]);
+    let mut word = ((state >> ((state >> 28) + 4)) ^ state).wrapping_mul(277803737);
+    word ^= word >> 22;
+    (word >> 8) as f32 / 16777216.0
+}
+
+/// the travelling waves of `curl_noise`: direction, frequency, speed, phase and amplitude
+const WAVES: [([f32; 2], f32, f32, f32, f32); 4] = [
+    ([0.8, 0.6], 1.3, 0.31, 0.0, 0.4),
+    ([-0.6, 0.8], 2.9, -0.47, 1.7, 0.3),
+    ([0.28, -0.96], 5.3, 0.73, 3.4, 0.2),
+    ([-0.96, -0.28], 11.0, -1.1, 5.1, 0.1),
+];
+
+/// turbulence without sources or sinks, the curl of a few travelling waves
+fn curl_noise(p: Vector2<f32>, t: f32) -> Vector2<f32> {
+    WAVES.iter()
+        .map(|&(dir, freq, speed, phase, amp)| {
+            let dir = Vector2::from(dir);
+            amp * (freq * dir.dot(p) + speed * t + phase).cos() * Vector2::new(dir.y, -dir.x)
+        })
+    .fold(Vector2::new(0.0, 0.0), |a, b| a + b)
+}
+
+fn wind_at(pos: Vector2<f32>, data: &FrameData) -> Vector2<f32> {
+    let swirl = curl_noise(pos.mul_element_wise(Vector2::new(data.aspect, 1.0)), data.time);
+    Vector2::from(data.wind) + swirl * data.turbulence * (1.0 + data.gust)
+}
+
+fn cursor_push(pos: Vector2<f32>, data: &FrameData) -> Vector2<f32> {
+    let cursor = Vector2::from(data.cursor);
+    let cursor_vel = Vector2::from(data.cursor_vel);
+    let speed = cursor_vel.magnitude();
+    if speed < 0.001 { return Vector2::new(0.0, 0.0) }
+
+    let aspect = Vector2::new(data.aspect, 1.0);
+    let end = cursor.mul_element_wise(aspect);
+    let start = (cursor - cursor_vel * data.dt).mul_element_wise(aspect);
+    let path = end - start;
+    let p = pos.mul_element_wise(aspect);
+    let t = ((p - start).dot(path) / path.dot(path).max(0.00000001)).clamp(0.0, 1.0);
+    let offset = p - (start + path * t);
+    let dist = offset.magnitude();
+    if dist > CURSOR_RADIUS { return Vector2::new(0.0, 0.0) }
+
+    let away = (offset / dist.max(0.0001)).div_element_wise(aspect);
+    let falloff = 1.0 - dist / CURSOR_RADIUS;
+    (cursor_vel * 0.6 + away * speed * 0.4) * falloff
+}
+
+fn drag(vel: Vector2<f32>, data: &FrameData) -> Vector2<f32> {
+    let speed = vel.magnitude();
+    if speed <= data.max_speed { return vel }
+    vel * (data.max_speed / speed).max((-data.drag * data.dt).exp())
+}
+
+struct Bounds {
+    left: f32,
+    right: f32,
+    top: f32,
+    bottom: f32,
+}
+
+impl Bounds {
+    fn new(rect: &RectInstance) -> Self {
+        Self {
+            left: rect.pos[0] * 2.0 - 1.0,
+            right: (rect.pos[0] + rect.dim[0]) * 2.0 - 1.0,
+            top: -(rect.pos[1] * 2.0 - 1.0),
+            bottom: -((rect.pos[1] + rect.dim[1]) * 2.0 - 1.0),
+        }
+    }
+
+    fn contains(&self, p: Vector2<f32>) -> bool {
+        p.x >= self.left && p.x <= self.right
+            && p.y <= self.top && p.y >= self.bottom
+    }
+}
+
+struct Landing {
+    rest: f32,
+    /// index into the heightfield
+    cell: usize,
+    /// of a heightfield column
+    width: f32,
+}
+
+/// lands on the cap on top of `bounds`, `windows` are the ones in front of it
+fn land_on(
+    bounds: &Bounds,
+    cap: u32,
+    windows: &[RectInstance],
+    caps: &Caps,
+    prev: Vector2<f32>,
+    pos: Vector2<f32>,
+    offset: f32,
+) -> Option<Landing> {
+    if pos.x < bounds.left || pos.x > bounds.right { return None }
+    let t = (pos.x - bounds.left) / (bounds.right - bounds.left);
+    let column = ((t.max(0.0) * CAP_COLUMNS as f32) as usize).min(CAP_COLUMNS - 1);
+    let cell = cap as usize * CAP_COLUMNS + column;
+    let rest = bounds.top + caps.heights[cell] as f32 * CAP_UNIT + offset;
+    if prev.y < bounds.top + offset - LAND_EPSILON || pos.y > rest { return None }
+    let covered = windows.iter()
+        .any(|v| Bounds::new(v).contains(Vector2::new(pos.x, bounds.top)));
+    (!covered).then_some(Landing {
+        rest, cell,
+        width: (bounds.right - bounds.left) / CAP_COLUMNS as f32,
+    })
+}
+
+/// where a flake moving from `prev` to `pos` lands
+fn land(
+    windows: &[RectInstance],
+    caps: &Caps,
+    prev: Vector2<f32>,
+    pos: Vector2<f32>,
+    offset: f32,
+) -> Option<Landing> {
+    windows.iter().enumerate()
+        .find_map(|(w, rect)| {
+            land_on(&Bounds::new(rect), rect.cap, &windows[..w], caps, prev, pos, offset)
+        })
+        .or_else(|| {
+            // the bottom of the screen, behind all windows
+            let bottom = Bounds { left: -1.0, right: 1.0, top: -1.0, bottom: -1.0 };
+            land_on(&bottom, 0, windows, caps, prev, pos, offset)
+        })
+}
+
+/// turns instance `i` into the snow of a column of a shed cap, if there is
+/// any. every shed cap takes `CAP_COLUMNS` instances, one per column
+fn shed(i: usize, instance: &mut SnowflakeInstance, data: &FrameData, caps: &Caps) -> bool {
+    let Some(shed) = caps.shed.get(i / CAP_COLUMNS) else { return false };
+    let column = i % CAP_COLUMNS;
+    let height = caps.heights[shed.cap as usize * CAP_COLUMNS + column] as f32 * CAP_UNIT;
+    if shed.cap == 0 || height <= 0.0 { return false }
+
+    // as big as the flakes that settled there
+    let width = shed.dim[0] * 2.0 / CAP_COLUMNS as f32;
+    let size = (height * width / CAP_GAIN).sqrt();
+    let x = (shed.pos[0] + (column as f32 + 0.5) / CAP_COLUMNS as f32 * shed.dim[0]) * 2.0 - 1.0;
+    let top = -(shed.pos[1] * 2.0 - 1.0);
+    instance.pos = [x, top + height * 0.5];
+    instance.vel = shed.vel;
+    instance.scale = size / depth_scale(instance.depth, data.parallax);
+    instance.age = 0.0;
+    true
+}
+
+/// advances every instance by one frame. `windows` are sorted front to back,
+/// flakes that settled are added to the pending snow of `caps`
+pub fn step(
+    instances: &mut [SnowflakeInstance],
+    data: &FrameData,
+    windows: &[RectInstance],
+    caps: &mut Caps,
+) {
+    let gravity = Vector2::from(data.gravity);
+    let windows = &windows[..windows.len().min(data.window_count as usize)];
+
+    for (i, instance) in instances.iter_mut().enumerate() {
+        if shed(i, instance, data, caps) { continue }
+
+        let mut pos = Vector2::from(instance.pos);
+        let mut vel = Vector2::from(instance.vel);
+        if vel.magnitude() < 0.01 {
+            instance.age += data.dt;
+        }
+
+        let prev = pos;
+        let wind = wind_at(pos, data);
+        // swaying from side to side as it spins
+        let flutter = Vector2::new(instance.angle.sin() * data.flutter, 0.0);
+        vel = drag(vel + cursor_push(pos, data), data);
+        pos += vel * data.dt * 0.9;
+        // near flakes look bigger and move faster
+        let size = instance.scale * depth_scale(instance.depth, data.parallax);
+        vel += (gravity + wind + flutter) * data.dt * size;
+
+        // sit on the edge instead of the center
+        let mut settled = false;
+        let landing = land(windows, caps, prev, pos, size * 0.5);
+        if let Some(landing) = &landing {
+            pos.y = landing.rest;
+            vel = Vector2::new(0.0, 0.0);
+            // rested long enough, becomes part of the cap
+            if instance.age > SETTLE_TIME {
+                let height = size * size * CAP_GAIN / landing.width;
+                let pending = &mut caps.pending[landing.cell];
+                *pending = pending.wrapping_add((height / CAP_UNIT) as u32);
+                settled = true;
+            }
+        }
+        // resting flakes stop spinning
+        if landing.is_none() {
+            instance.angle = (instance.angle + instance.spin * data.dt) % TAU;
+        }
+
+        let fell = pos.y + PADDING < -1.0;
+        if fell || settled || instance.age > data.max_age {
+            // the draws in the order of the shader, don't add or reorder any
+            let [min_size, max_size] = data.flake_size;
+            instance.scale = min_size + (max_size - min_size) * rand(&mut instance.rng);
+            pos.x = rand(&mut instance.rng) * 2.0 - 1.0;
+            instance.angle = rand(&mut instance.rng) * TAU;
+            instance.spin = (rand(&mut instance.rng) * 2.0 - 1.0) * data.max_spin;
+            // the 24 bits of a `rand`
+            instance.shape = (rand(&mut instance.rng) * 16777216.0) as u32;
+            instance.sprite = (rand(&mut instance.rng) * 16777216.0) as u32;
+            // more far flakes than near ones
+            instance.depth = rand(&mut instance.rng).sqrt();
+            if fell {
+                pos.y += 2.0 * (1.0 + PADDING);
+            } else {
+                pos.y = 1.0 + PADDING;
+            }
+            vel = Vector2::new(0.0, 0.0);
+            instance.age = 0.0;
+        }
+
+        // also works for all components
+        pos.x = (((pos.x + 1.0) / 2.0 + 1.0) % 1.0) * 2.0 - 1.0;
+
+        instance.pos = pos.into();
+        instance.vel = vel.into();
+    }
+}
+
+/// merges the flakes that settled into the caps and melts them a bit
+pub fn melt(caps: &mut Caps, data: &FrameData) {
+    let melted = (data.melt_rate * data.dt / CAP_UNIT) as u32;
+    let max = (CAP_MAX / CAP_UNIT) as u32;
+    for (height, pending) in caps.heights.iter_mut().zip(&mut caps.pending) {
+        let merged = height.wrapping_add(std::mem::take(pending)).min(max);
+        *height = merged - merged.min(melted);
+    }
+    // their snow is falling as flakes now
+    for shed in &caps.shed {
+        if shed.cap == 0 { continue }
+        let row = shed.cap as usize * CAP_COLUMNS;
+        caps.heights[row..row + CAP_COLUMNS].fill(0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+    use crate::{config::SnowConfig, headless::{self, HeadlessState}, preset::Preset, snow::SimBackend};
+
+    const DT: f32 = 1.0 / 60.0;
+    const STEPS: usize = 120;
+    const EPSILON: f32 = 1e-4;
+    const CAP_TOLERANCE: u32 = 16;
+
+    fn frame_data(window_count: u32) -> FrameData {
+        FrameData {
+            dt: DT,
+            time: 0.0,
+            gravity: [0.1, -1.0],
+            aspect: 1.0,
+            max_age: 100.0,
+            window_count,
+            melt_rate: 0.002,
+            cursor: [0.0, 0.0],
+            cursor_vel: [0.0, 0.0],
+            flake_size: [0.001, 0.015],
+            active_count: 0,
+            // depth doesn't matter unless a test sets it
+            parallax: 0.0,
+            wind: [0.0, 0.0],
+            turbulence: 0.5,
+            gust: 0.0,
+            depth_of_field: 1.0,
+            depth_fade: 0.5,
+            style: 0,
+            max_speed: 0.5,
+            flake_color: [1.0; 4],
+            cap_color: [1.0; 4],
+            drag: 4.0,
+            flutter: 0.0,
+            max_spin: 1.5,
+            palette_count: 0,
+            palette: [[0.0; 4]; 4],
+        }
+    }
+
+    fn rect(pos: [f32; 2], dim: [f32; 2], cap: u32) -> RectInstance {
+        RectInstance { pos, dim, cap, _padding: 0 }
+    }
+
+    fn flake(pos: [f32; 2], vel: [f32; 2]) -> SnowflakeInstance {
+        SnowflakeInstance {
+            pos, vel,
+            scale: 0.01,
+            age: 0.0,
+            rng: [1, 1],
+            angle: 0.0,
+            spin: 1.0,
+            shape: 0,
+            sprite: 0,
+            depth: 0.5,
+            _padding: 0,
+        }
+    }
+
+    fn assert_close(cpu: &SnowflakeInstance, gpu: &SnowflakeInstance, i: usize) {
+        let close = |a: f32, b: f32| (a - b).abs() <= EPSILON;
+        assert!(
+            close(cpu.pos[0], gpu.pos[0]) && close(cpu.pos[1], gpu.pos[1])
+                && close(cpu.vel[0], gpu.vel[0]) && close(cpu.vel[1], gpu.vel[1])
+                && close(cpu.scale, gpu.scale) && close(cpu.age, gpu.age)
+                && close(cpu.angle, gpu.angle) && close(cpu.spin, gpu.spin)
+                && close(cpu.depth, gpu.depth)
+                && cpu.rng == gpu.rng && cpu.shape == gpu.shape && cpu.sprite == gpu.sprite,
+            "instance {i} diverged\n cpu: {cpu:?}\n gpu: {gpu:?}",
+        );
+    }
+
+    #[test]
+    fn matches_compute_shader() {
+        let Some((device, queue, sim)) = headless::test_device() else { return };
+        if sim != SimBackend::Gpu {
+            tracing::warn!("skipping test, adapter has no compute shaders");
+            return;
+        }
+        let mut state = HeadlessState::new(&device, 64, 64, &SnowConfig::default(), 0x5eed, SimBackend::Gpu);
+        let windows = [
+            rect([0.1, 0.3], [0.5, 0.4], 1),
+            rect([0.4, 0.5], [0.5, 0.3], 2),
+        ];
+        state.snow_mut().write_windows(&queue, &windows);
+        // fast enough to hit the windows and respawn a few flakes
+        let frame_data = state.snow_mut().frame_data_mut();
+        frame_data.gravity = [0.1, -200.0];
+        frame_data.wind = [20.0, 5.0];
+        frame_data.gust = 0.5;
+        frame_data.flutter = 5.0;
+
+        let mut cpu = state.snow().read_instances(&device, &queue);
+        let mut caps = Caps::new(state.snow().max_windows() + 1);
+        for i in 0..STEPS {
+            // and a cursor sweeping through them
+            let frame_data = state.snow_mut().frame_data_mut();
+            frame_data.cursor = [i as f32 / STEPS as f32 * 2.0 - 1.0, 0.2];
+            frame_data.cursor_vel = [2.0 / STEPS as f32 / DT, 0.0];
+            // and the first window shaking off its cap near the end
+            let shed = match i {
+                110 => vec![ShedCap { pos: windows[0].pos, dim: windows[0].dim, vel: [0.3, 0.1], cap: 1, _padding: 0 }],
+                _ => Vec::new(),
+            };
+            state.snow_mut().shed_caps(&queue, &shed);
+            caps.shed = shed;
+            state.render(&device, &queue, DT);
+            step(&mut cpu, state.snow().frame_data(), &windows, &mut caps);
+            melt(&mut caps, state.snow().frame_data());
+        }
+        let gpu = state.snow().read_instances(&device, &queue);
+        let gpu_caps = state.snow().read_caps(&device, &queue);
+
+        assert_eq!(cpu.len(), gpu.len());
+        for (i, (cpu, gpu)) in cpu.iter().zip(&gpu).enumerate() {
+            assert_close(cpu, gpu, i);
+        }
+        assert!(caps.heights.iter().any(|v| *v > 0), "nothing settled");
+        for (i, (cpu, gpu)) in caps.heights.iter().zip(&gpu_caps).enumerate() {
+            // a settling flake might round differently
+            assert!(cpu.abs_diff(*gpu) <= CAP_TOLERANCE, "cap cell {i} diverged, cpu: {cpu}, gpu: {gpu}");
+        }
+    }
+
+    #[test]
+    fn reload_keeps_flakes() {
+        let Some((device, queue, sim)) = headless::test_device() else { return };
+        let mut config = SnowConfig { particle_count: 100, ..Default::default() };
+        let mut state = HeadlessState::new(&device, 64, 64, &config, 0x5eed, sim);
+        state.render(&device, &queue, DT);
+        let before = state.snow().read_instances(&device, &queue);
+
+        config.particle_count = 150;
+        config.gravity = [0.0, -2.0];
+        state.snow_mut().apply_config(&device, &queue, &config);
+        let grown = state.snow().read_instances(&device, &queue);
+        assert_eq!(grown.len(), 150);
+        assert_eq!(state.snow().frame_data().gravity, [0.0, -2.0]);
+        assert!(before.iter().zip(&grown).all(|(a, b)| a.pos == b.pos && a.rng == b.rng));
+        // the new flakes continue the rng streams
+        assert!(grown[100..].iter().enumerate().all(|(i, v)| v.rng[1] == ((100 + i) as u32) << 1 | 1));
+
+        config.particle_count = 50;
+        state.snow_mut().apply_config(&device, &queue, &config);
+        state.render(&device, &queue, DT);
+        assert_eq!(state.snow().read_instances(&device, &queue).len(), 50);
+
+        // but not across presets
+        let leaves = config.with_preset(Preset::Leaves);
+        state.snow_mut().apply_config(&device, &queue, &leaves);
+        let [min, max] = leaves.flake_size;
+        let respawned = state.snow().read_instances(&device, &queue);
+        assert_eq!(respawned.len(), 50);
+        assert!(respawned.iter().all(|v| (min..=max).contains(&v.scale)));
+    }
+
+    #[test]
+    fn steps_only_active_flakes() {
+        let Some((device, queue, sim)) = headless::test_device() else { return };
+        let config = SnowConfig { particle_count: 600, ..Default::default() };
+        let mut state = HeadlessState::new(&device, 64, 64, &config, 0x5eed, sim);
+        state.snow_mut().set_active_count(300);
+        let before = state.snow().read_instances(&device, &queue);
+        state.render(&device, &queue, DT);
+        let after = state.snow().read_instances(&device, &queue);
+
+        assert!(before[..300].iter().zip(&after).all(|(a, b)| a.vel != b.vel));
+        assert_eq!(before[300..], after[300..]);
+    }
+
+    #[test]
+    fn wraps_and_respawns() {
+        let data = frame_data(0);
+        let mut instances = [
+            // leaves through the right edge
+            flake([0.999, 0.0], [1.0, 0.0]),
+            // fell below the screen
+            SnowflakeInstance { age: 1.0, rng: [2, 3], ..flake([0.0, -1.2], [0.0, -0.1]) },
+            // resting too long
+            SnowflakeInstance { age: 100.0, rng: [3, 5], ..flake([0.0, 0.0], [0.0, 0.0]) },
+        ];
+        step(&mut instances, &data, &[], &mut Caps::new(1));
+
+        assert!(instances[0].pos[0] < -0.9, "did not wrap: {:?}", instances[0]);
+        assert_eq!(instances[0].angle, DT);
+        for instance in &instances[1..] {
+            assert_eq!(instance.age, 0.0);
+            assert_eq!(instance.vel, [0.0, 0.0]);
+            assert!((-1.0..=1.0).contains(&instance.pos[0]));
+            assert!((0.001..=0.015).contains(&instance.scale));
+            // and a new crystal
+            assert!((0.0..TAU).contains(&instance.angle) && instance.spin.abs() <= data.max_spin);
+            assert_ne!(instance.shape, 0);
+            assert!((0.0..=1.0).contains(&instance.depth) && instance.depth != 0.5);
+        }
+        assert!(instances[1].pos[1] > 0.9);
+        assert_eq!(instances[0].rng, [1, 1], "rng advanced without a respawn");
+    }
+
+    #[test]
+    fn respawns_are_deterministic() {
+        let data = frame_data(0);
+        let fallen = SnowflakeInstance { age: 1.0, rng: [7, 9], ..flake([0.0, -1.2], [0.0, -0.1]) };
+
+        let mut a = [fallen; 2];
+        a[1].rng = [8, 9];
+        let mut b = a;
+        step(&mut a, &data, &[], &mut Caps::new(1));
+        step(&mut b, &data, &[], &mut Caps::new(1));
+
+        assert_eq!(a, b);
+        assert_ne!(a[0].pos, a[1].pos, "different rng state, same respawn");
+    }
+
+    #[test]
+    fn lands_on_visible_window_tops() {
+        // top edge at y = 0.5, x in [-0.5, 0.5]
+        let back = rect([0.25, 0.25], [0.5, 0.5], 2);
+        // covers the left half of the back window top
+        let front = rect([0.0, 0.0], [0.5, 0.5], 1);
+        let windows = [front, back];
+        let data = frame_data(2);
+        let mut caps = Caps::new(3);
+
+        let mut instances = [
+            // falls onto the visible part of the back window
+            flake([0.25, 0.51], [0.0, -3.0]),
+            // falls through the covered part onto the front window face
+            flake([-0.25, 0.51], [0.0, -3.0]),
+        ];
+        step(&mut instances, &data, &windows, &mut caps);
+
+        assert_eq!(instances[0].pos[1], 0.5 + 0.005);
+        assert_eq!(instances[0].vel, [0.0, 0.0]);
+        // and stopped spinning
+        assert_eq!(instances[0].angle, 0.0);
+        assert!(instances[1].pos[1] < 0.5);
+
+        // keeps resting while the window stays
+        for _ in 0..10 { step(&mut instances, &data, &windows, &mut caps) }
+        assert_eq!(instances[0].pos[1], 0.5 + 0.005);
+
+        // and falls once it is gone
+        let data = frame_data(0);
+        for _ in 0..10 { step(&mut instances, &data, &windows, &mut caps) }
+        assert!(instances[0].pos[1] < 0.5 + 0.005);
+    }
+
+    #[test]
+    fn settled_flakes_build_caps() {
+        let windows = [rect([0.25, 0.25], [0.5, 0.5], 1)];
+        let data = frame_data(1);
+        let mut caps = Caps::new(2);
+
+        let mut instances = [
+            flake([0.0, 0.51], [0.0, -3.0]),
+            // lands on the bottom of the screen
+            flake([0.9, -0.99], [0.0, -3.0]),
+        ];
+        let steps = (SETTLE_TIME / DT) as usize + 4;
+        for _ in 0..steps {
+            step(&mut instances, &data, &windows, &mut caps);
+            melt(&mut caps, &data);
+        }
+
+        // both settled and respawned at the top
+        assert!(instances.iter().all(|v| v.pos[1] > 1.0), "{instances:?}");
+        let window_cap = &caps.heights[CAP_COLUMNS..];
+        let bottom_cap = &caps.heights[..CAP_COLUMNS];
+        assert!(window_cap[CAP_COLUMNS / 2] > 0);
+        assert!(bottom_cap[CAP_COLUMNS * 19 / 20] > 0);
+        assert_eq!(window_cap.iter().filter(|v| **v > 0).count(), 1);
+
+        // the next flake rests on top of the cap
+        let height = window_cap[CAP_COLUMNS / 2] as f32 * CAP_UNIT;
+        let mut instances = [flake([0.0, 0.51 + height], [0.0, -3.0])];
+        step(&mut instances, &data, &windows, &mut caps);
+        assert_eq!(instances[0].pos[1], 0.5 + height + 0.005);
+
+        // and it melts
+        let data = FrameData { dt: 1.0, melt_rate: CAP_MAX, ..data };
+        melt(&mut caps, &data);
+        assert!(caps.heights.iter().all(|v| *v == 0));
+    }
+
+    #[test]
+    fn moved_windows_shed_their_caps() {
+        let mut data = frame_data(1);
+        data.turbulence = 0.0;
+        let window = rect([0.25, 0.25], [0.5, 0.5], 1);
+        let mut caps = Caps::new(2);
+        for column in [10, 40] {
+            caps.heights[CAP_COLUMNS + column] = (0.01 / CAP_UNIT) as u32;
+        }
+        let cap_mass: f32 = caps.heights[CAP_COLUMNS..].iter()
+            .map(|v| *v as f32 * CAP_UNIT * 1.0 / CAP_COLUMNS as f32)
+        .sum();
+
+        // dragged to the bottom right
+        let moved = rect([0.6, 0.7], [0.3, 0.2], 1);
+        caps.shed = vec![ShedCap { pos: window.pos, dim: window.dim, vel: [0.2, 0.0], cap: 1, _padding: 0 }];
+        let mut instances = [flake([0.0, 0.9], [0.0, 0.0]); CAP_COLUMNS];
+        step(&mut instances, &data, &[moved], &mut caps);
+        melt(&mut caps, &data);
+        assert!(caps.heights[CAP_COLUMNS..].iter().all(|v| *v == 0));
+
+        // a flake for every column with snow, where it was
+        let shed = instances.iter().enumerate()
+            .filter(|(_, v)| v.vel == [0.2, 0.0])
+        .collect::<Vec<_>>();
+        assert_eq!(shed.iter().map(|(i, _)| *i).collect::<Vec<_>>(), [10, 40]);
+        let (_, flake) = shed[0];
+        assert!((flake.pos[0] - (-0.5 + 10.5 / CAP_COLUMNS as f32)).abs() < 1e-6);
+        assert!(flake.pos[1] > 0.5 && flake.pos[1] < 0.52);
+        let flake_mass: f32 = shed.iter().map(|(_, v)| v.scale * v.scale).sum();
+        assert!((flake_mass - cap_mass).abs() < cap_mass * 1e-3, "{flake_mass} {cap_mass}");
+
+        // and they fall along
+        let start = flake.pos;
+        caps.shed.clear();
+        for _ in 0..30 { step(&mut instances, &data, &[moved], &mut caps) }
+        let flake = &instances[10];
+        assert!(flake.pos[0] > start[0] + 0.05 && flake.pos[1] < start[1] && flake.vel[1] < 0.0, "{flake:?}");
+    }
+
+    #[test]
+    fn cursor_pushes_flakes_away() {
+        let mut data = frame_data(0);
+        data.cursor = [0.0, 0.0];
+        data.cursor_vel = [1.0, 0.0];
+        let mut caps = Caps::new(1);
+
+        let mut instances = [
+            // right in front of the cursor
+            flake([0.01, 0.0], [0.0, 0.0]),
+            // just above its path
+            flake([0.0, 0.05], [0.0, 0.0]),
+            // too far away
+            flake([0.0, 0.5], [0.0, 0.0]),
+        ];
+        step(&mut instances, &data, &[], &mut caps);
+
+        assert!(instances[0].vel[0] > 0.1, "{:?}", instances[0]);
+        assert!(instances[1].vel[0] > 0.0 && instances[1].vel[1] > 0.0, "{:?}", instances[1]);
+        assert!(instances[2].vel[0].abs() < 0.01, "{:?}", instances[2]);
+
+        // and they slow down again
+        data.cursor_vel = [0.0, 0.0];
+        for _ in 0..120 { step(&mut instances, &data, &[], &mut caps) }
+        assert!(Vector2::from(instances[0].vel).magnitude() < data.max_speed + 0.01);
+    }
+
+    #[test]
+    fn turbulence_swirls() {
+        // no divergence, so flakes don't bunch up anywhere
+        let h = 1e-3;
+        for i in 0..50 {
+            let p = Vector2::new((i as f32 * 0.37).sin(), (i as f32 * 0.71).cos());
+            let t = i as f32 * 0.3;
+            let dx = curl_noise(p + Vector2::new(h, 0.0), t) - curl_noise(p - Vector2::new(h, 0.0), t);
+            let dy = curl_noise(p + Vector2::new(0.0, h), t) - curl_noise(p - Vector2::new(0.0, h), t);
+            assert!(((dx.x + dy.y) / (2.0 * h)).abs() < 0.05);
+        }
+
+        // but not all the same way
+        let mut data = frame_data(0);
+        data.gravity = [0.0, 0.0];
+        let mut instances = [flake([-0.5, 0.3], [0.0, 0.0]), flake([0.4, -0.2], [0.0, 0.0])];
+        step(&mut instances, &data, &[], &mut Caps::new(1));
+        let [a, b] = instances.map(|v| Vector2::from(v.vel));
+        assert!(a.magnitude() > 0.0 && b.magnitude() > 0.0);
+        assert!(a.normalize().dot(b.normalize()) < 0.99);
+
+        // the base wind and gusts push them along
+        data.turbulence = 0.0;
+        data.wind = [1.0, 0.0];
+        let mut instances = [flake([0.0, 0.0], [0.0, 0.0])];
+        step(&mut instances, &data, &[], &mut Caps::new(1));
+        assert!(instances[0].vel[0] > 0.0 && instances[0].vel[1] == 0.0);
+    }
+
+    #[test]
+    fn near_flakes_fall_faster() {
+        let mut data = frame_data(0);
+        data.parallax = 1.0;
+        data.turbulence = 0.0;
+        let at_depth = |depth| SnowflakeInstance { depth, ..flake([0.0, 0.5], [0.0, 0.0]) };
+        let mut instances = [at_depth(0.0), at_depth(1.0)];
+        for _ in 0..10 { step(&mut instances, &data, &[], &mut Caps::new(1)) }
+        let [near, far] = instances.map(|v| v.vel[1]);
+        assert!(near < far && far < 0.0, "near {near}, far {far}");
+        let ratio = depth_scale(0.0, 1.0) / depth_scale(1.0, 1.0);
+        assert!((near / far - ratio).abs() < 0.01);
+
+        // and all the same without parallax
+        data.parallax = 0.0;
+        let mut instances = [at_depth(0.0), at_depth(1.0)];
+        step(&mut instances, &data, &[], &mut Caps::new(1));
+        assert_eq!(instances[0].vel, instances[1].vel);
+    }
+
+    #[test]
+    fn flutter_sways_with_the_spin() {
+        let mut data = frame_data(0);
+        data.gravity = [0.0, 0.0];
+        data.turbulence = 0.0;
+        data.flutter = 10.0;
+        let at_angle = |angle| SnowflakeInstance { angle, spin: 0.0, ..flake([0.0, 0.0], [0.0, 0.0]) };
+        let mut instances = [at_angle(0.5 * PI), at_angle(1.5 * PI), at_angle(0.0)];
+        step(&mut instances, &data, &[], &mut Caps::new(1));
+        let [right, left, still] = instances.map(|v| v.vel);
+        assert!(right[0] > 0.0 && left[0] < 0.0 && still[0].abs() < 1e-6);
+        assert!([right, left, still].iter().all(|v| v[1] == 0.0));
+    }
+}
@@ -0,0 +1,44 @@
+use cgmath::{Vector2, Zero};
+
+
+/// something that knows where the cursor is, in global screen coordinates
+/// with the same unit as `AppWindow`. the overlay ignores the cursor, so
+/// it never gets any cursor events and has to poll instead
+pub trait CursorSource {
+    fn position(&mut self) -> Option<(f64, f64)>;
+}
+
+/// for platforms that can not see the cursor outside of their own windows
+#[derive(Debug, Default)]
+pub struct NoCursor;
+
+impl CursorSource for NoCursor {
+    fn position(&mut self) -> Option<(f64, f64)> { None }
+}
+
+/// the velocity of the polled cursor, smoothed over a few frames
+#[derive(Debug)]
+pub struct CursorMotion {
+    last: Option<Vector2<f32>>,
+    vel: Vector2<f32>,
+}
+
+impl Default for CursorMotion {
+    fn default() -> Self {
+        Self { last: None, vel: Vector2::zero() }
+    }
+}
+
+impl CursorMotion {
+    /// `pos` is in simulation space, the velocity is per second
+    pub fn update(&mut self, pos: Option<[f32; 2]>, dt: f32) -> [f32; 2] {
+        let pos = pos.map(Vector2::from);
+        let vel = match (self.last, pos) {
+            (Some(last), Some(pos)) if dt > 0.0 => (pos - last) / dt,
+            _ => Vector2::zero(),
+        };
+        self.last = pos;
+        self.vel = self.vel * 0.5 + vel * 0.5;
+        self.vel.into()
+    }
+}
@@ -1,84 +1,594 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Arc, atomic::{AtomicBool, Ordering}},
+    time::{Duration, Instant},
+};
 
-use icrate::{Foundation::MainThreadMarker, AppKit::NSScreen};
-use winit::{window::{Window, WindowId}, event_loop::EventLoop, event::WindowEvent};
+use wgpu::core::device::{DeviceError, queue::{QueueSubmitError, QueueWriteError}};
+use winit::{
+    window::{Window, WindowId},
+    event_loop::{ControlFlow, EventLoopWindowTarget},
+    event::WindowEvent,
+    dpi::PhysicalSize,
+};
 
-use crate::snow::{SnowState, BuildError};
+use crate::{
+    command::{Command, Controls, MenuEntry},
+    config::{Config, ConfigWatcher, SnowConfig, MAX_PARTICLES},
+    pacing::{FramePacer, DEFAULT_FPS},
+    platform,
+    power::{PowerMode, PowerPolicy},
+    shader::ShaderWatcher,
+    snow::{Simulation, SnowState, BuildError},
+    windows::Region,
+};
 
 
+/// how often to look for added, removed or resized monitors
+const MONITOR_INTERVAL: Duration = Duration::from_secs(2);
+/// how often to try to get a new device after it was lost
+const RECOVERY_INTERVAL: Duration = Duration::from_secs(1);
+/// how often to check the battery
+const POWER_INTERVAL: Duration = Duration::from_secs(10);
+/// how long to sleep while nothing is drawing, the control
+/// socket and the menu are only handled in between
+const IDLE_INTERVAL: Duration = Duration::from_millis(100);
+
 pub struct State {
     instance: wgpu::Instance,
     adapter: wgpu::Adapter,
     device: wgpu::Device,
     queue: wgpu::Queue,
+    /// set from the error handler of the device, a new one for every device
+    device_lost: Arc<AtomicBool>,
+    last_recovery: Option<Instant>,
+    seed: u64,
 
     states: HashMap<WindowId, SnowState>,
+    /// the snow across all monitors when `Config::shared` is set
+    shared: Option<Simulation>,
+    /// in monitor order, with the name the config can select by
+    monitors: Vec<(WindowId, Option<String>)>,
+    monitor_check: Instant,
+    /// as loaded, before the controls are applied
+    config: Config,
+    config_path: Option<PathBuf>,
+    config_watcher: Option<ConfigWatcher>,
+    shader_watcher: Option<ShaderWatcher>,
+    controls: Controls,
+    power: PowerPolicy,
+    pacer: FramePacer,
 }
 
 
 impl State {
     pub async fn new<E>(
-        main_thread: MainThreadMarker,
-        event_loop: &EventLoop<E>,
+        event_loop: &EventLoopWindowTarget<E>,
+        seed: u64,
+        config: &Config,
     ) -> Result<Self, BuildError> {
         let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
             backends: wgpu::Backends::all(),
             ..Default::default()
         });
 
-        let adapter = instance.request_adapter(&wgpu::RequestAdapterOptions {
-            power_preference: wgpu::PowerPreference::default(),
-            compatible_surface: None,
-            force_fallback_adapter: false,
-        }).await.expect("could not find adapter");
-
-        let (device, queue) = adapter.request_device(&wgpu::DeviceDescriptor {
-            features: wgpu::Features::empty(),
-            limits: wgpu::Limits::default(),
-            label: Some("render_device"),
-        }, None).await.expect("could not get device");
-
-        let states = NSScreen::screens(main_thread).into_iter()
-            .map(|m| {
-                let s = SnowState::new(
-                    &device, &instance,
-                    &adapter, 1000, m,
-                    &event_loop,
-                )?;
-                Ok((s.window_id(), s))
-            })
-        .collect::<Result<_, BuildError>>()?;
+        let (adapter, device, queue, device_lost) = request_device(&instance).await?;
 
-        Ok(Self {
+        let mut state = Self {
             instance,
-            adapter, device, queue,
-            states,
-        })
+            adapter, device, queue, seed,
+            device_lost,
+            last_recovery: None,
+            states: HashMap::new(),
+            shared: None,
+            monitors: Vec::new(),
+            monitor_check: Instant::now(),
+            controls: Controls::new([]),
+            config: config.clone(),
+            config_path: None,
+            config_watcher: None,
+            shader_watcher: None,
+            power: PowerPolicy::new(platform::power_source(), config.power.clone(), POWER_INTERVAL),
+            pacer: FramePacer::new(Duration::from_secs_f32(1.0 / DEFAULT_FPS)),
+        };
+        state.sync_monitors(event_loop)?;
+        Ok(state)
+    }
+
+    /// adds and removes overlays to match the connected monitors and
+    /// follows resolution changes. `true` if the monitors changed
+    pub fn sync_monitors<E>(
+        &mut self,
+        event_loop: &EventLoopWindowTarget<E>,
+    ) -> Result<bool, BuildError> {
+        self.monitor_check = Instant::now();
+        let previous = std::mem::take(&mut self.monitors);
+        let enabled = previous.iter().zip(&self.controls.monitors)
+            .map(|((id, _), (_, enabled))| (*id, *enabled))
+        .collect::<HashMap<_, _>>();
+
+        let mut error = None;
+        let mut stale = previous.clone();
+        for (i, monitor) in platform::monitors(event_loop).into_iter().enumerate() {
+            let name = platform::monitor_name(&monitor);
+            let existing = stale.iter().position(|(id, _)| {
+                self.states.get(id).is_some_and(|v| platform::same_monitor(v.monitor(), &monitor))
+            });
+            if let Some(j) = existing {
+                let (id, _) = stale.remove(j);
+                if let Some(state) = self.states.get_mut(&id) {
+                    state.set_monitor(monitor);
+                }
+                self.monitors.push((id, name));
+                continue;
+            }
+
+            tracing::info!("monitor added: {name:?}");
+            let config = monitor_config(&self.config, i, name.as_deref(), &self.controls);
+            // every monitor gets its own, but still reproducible, snow
+            let state = SnowState::new(
+                &self.device, &self.queue, &self.instance,
+                &self.adapter, &config, self.config.frame.vsync,
+                self.seed.wrapping_add(i as u64), monitor,
+                platform::window_source(),
+                platform::cursor_source(),
+                event_loop,
+            );
+            match state {
+                Ok(state) => {
+                    self.monitors.push((state.window_id(), name));
+                    self.states.insert(state.window_id(), state);
+                },
+                Err(e) => error = Some(e),
+            }
+        }
+        for (id, name) in stale {
+            tracing::info!("monitor removed: {name:?}");
+            self.states.remove(&id);
+        }
+        self.share();
+
+        self.controls.monitors = self.monitors.iter().enumerate()
+            .map(|(i, (id, name))| (
+                name.clone().unwrap_or_else(|| format!("Monitor {}", i + 1)),
+                enabled.get(id).copied().unwrap_or(true),
+            ))
+        .collect();
+        let changed = self.monitors != previous;
+        if changed {
+            self.apply_running();
+        }
+        // the refresh rate can change without anything else
+        self.update_pacing();
+        error.map_or(Ok(changed), Err)
+    }
+
+    /// in shared mode, switches new monitors over to the shared snow
+    /// and fits it to the monitors
+    fn share(&mut self) {
+        if !self.config.shared { return }
+        for (id, _) in &self.monitors {
+            if let Some(state) = self.states.get_mut(id) {
+                state.share(&self.device, &mut self.shared);
+            }
+        }
+        self.update_views();
+    }
+
+    fn update_views(&mut self) {
+        let Some(shared) = &mut self.shared else { return };
+        let Some(desktop) = self.states.values().map(|v| v.region()).reduce(Region::union) else {
+            return;
+        };
+        if desktop != shared.region() {
+            tracing::info!("shared snow: {desktop:?}");
+            shared.set_region(desktop);
+        }
+        for state in self.states.values_mut() {
+            state.update_view(&self.queue, shared);
+        }
+    }
+
+    /// `sync_monitors`, at most every `MONITOR_INTERVAL`
+    pub fn poll_monitors<E>(
+        &mut self,
+        event_loop: &EventLoopWindowTarget<E>,
+    ) -> Result<bool, BuildError> {
+        if self.monitor_check.elapsed() < MONITOR_INTERVAL {
+            return Ok(false);
+        }
+        self.sync_monitors(event_loop)
+    }
+
+    /// reloads the config whenever `path` changes
+    pub fn watch_config(&mut self, path: PathBuf) {
+        tracing::info!("watching {path:?}");
+        self.config_watcher = Some(ConfigWatcher::new(path.clone(), Duration::from_secs(1)));
+        self.config_path = Some(path);
+    }
+
+    /// checks the battery, `true` if the power mode changed
+    pub fn poll_power(&mut self) -> bool {
+        if self.power.poll().is_none() {
+            return false;
+        }
+        self.apply_running();
+        self.update_pacing();
+        true
     }
 
-    pub fn redraw(&self) {
+    /// sleeps until the next frame is due
+    pub fn control_flow(&self) -> ControlFlow {
+        if !self.states.values().any(|v| v.running()) {
+            return ControlFlow::wait_duration(IDLE_INTERVAL);
+        }
+        ControlFlow::WaitUntil(self.pacer.next_frame())
+    }
+
+    /// the configured frame rate, otherwise the one of the fastest
+    /// monitor, and no faster than the battery allows
+    fn update_pacing(&mut self) {
+        let fps = self.config.frame.fps.unwrap_or_else(|| {
+            self.states.values()
+                .filter_map(|v| platform::refresh_rate(v.monitor()))
+                .reduce(f32::max)
+            .unwrap_or(DEFAULT_FPS)
+        });
+        let interval = Duration::from_secs_f32(1.0 / fps);
+        let interval = self.power.mode().frame_interval().map_or(interval, |v| v.max(interval));
+        self.pacer.set_interval(interval);
+        for sim in sims_mut(&mut self.states, &mut self.shared) {
+            sim.set_frame_interval(interval);
+        }
+    }
+
+    /// rebuilds the pipelines whenever a shader in `dir` changes
+    pub fn watch_shaders(&mut self, dir: PathBuf) {
+        tracing::info!("watching shaders in {dir:?}");
+        self.shader_watcher = Some(ShaderWatcher::new(dir, Duration::from_millis(250)));
+    }
+
+    fn reload_shaders(&mut self) {
+        let Some(watcher) = &mut self.shader_watcher else { return };
+        for (shader, src) in watcher.poll() {
+            for sim in sims_mut(&mut self.states, &mut self.shared) {
+                // every monitor compiles the same source, one error is enough
+                if let Err(e) = sim.snow_mut().reload_shader(&self.device, shader, &src) {
+                    tracing::error!("keeping the old {}: {e}", shader.file_name());
+                    break;
+                }
+            }
+        }
+    }
+
+    fn apply_config(&mut self, mut config: Config) {
+        if config.shared != self.config.shared {
+            tracing::warn!("shared only changes after a restart");
+            config.shared = self.config.shared;
+        }
+        for (i, (id, name)) in self.monitors.iter().enumerate() {
+            if let Some(sim) = self.states.get_mut(id).and_then(|v| v.sim_mut()) {
+                let config = monitor_config(&config, i, name.as_deref(), &self.controls);
+                sim.apply_config(&self.device, &self.queue, &config);
+            }
+        }
+        if let Some(shared) = &mut self.shared {
+            let config = monitor_config(&config, 0, None, &self.controls);
+            shared.apply_config(&self.device, &self.queue, &config);
+        }
+        if config.frame.vsync != self.config.frame.vsync {
+            for state in self.states.values_mut() {
+                state.set_vsync(&self.device, &self.adapter, config.frame.vsync);
+            }
+        }
+        self.power.set_config(config.power.clone());
+        self.config = config;
+        self.apply_running();
+        self.update_pacing();
+    }
+
+    /// pauses or hides the monitors to match the controls and the battery
+    fn apply_running(&mut self) {
+        let power_paused = self.power.mode() == PowerMode::Paused;
+        for (i, (id, _)) in self.monitors.iter().enumerate() {
+            let Some(state) = self.states.get_mut(id) else { continue };
+            let running = self.controls.running(i) && !power_paused;
+            if state.running() != running {
+                state.set_running(running);
+                if let (true, Some(shared)) = (running, &mut self.shared) {
+                    shared.resume();
+                }
+            }
+            // a disabled monitor should not keep showing its last frame
+            state.fg_window.set_visible(self.controls.monitors[i].1);
+        }
+    }
+
+    /// the status bar menu for the current controls
+    pub fn menu(&self) -> Vec<MenuEntry> {
+        self.controls.menu()
+    }
+
+    /// requests the next frame, once it is due
+    pub fn redraw(&mut self) {
+        if !self.pacer.frame_due(Instant::now()) { return }
         for state in self.states.values() {
             state.redraw();
         }
     }
 
+    /// everything but `Command::Quit`, that is up to the event loop
+    pub fn handle(&mut self, command: Command) -> Result<String, String> {
+        self.controls.apply(command)?;
+        match command {
+            Command::Pause | Command::Resume | Command::SetMonitorEnabled(..) => {
+                self.apply_running();
+            },
+            Command::SetIntensity(_) | Command::SetPreset(_) | Command::SetGravity(_) => {
+                self.apply_config(self.config.clone());
+            },
+            Command::ReloadConfig => {
+                let config = Config::load(self.config_path.as_deref())
+                    .map_err(|e| e.to_string())?;
+                self.apply_config(config);
+            },
+            Command::SetParticles(count) => for sim in sims_mut(&mut self.states, &mut self.shared) {
+                sim.set_particle_count(&self.device, &self.queue, count);
+            },
+            Command::Stats => return Ok(self.stats()),
+            Command::Quit => return Err("not handled here".to_string()),
+        }
+        Ok(String::new())
+    }
+
+    fn stats(&self) -> String {
+        self.monitors.iter().enumerate()
+            .filter_map(|(i, (id, name))| {
+                let state = self.states.get(id)?;
+                let snow = state.snow().or(self.shared.as_ref().map(|v| v.snow()))?;
+                Some((i, name, state, snow))
+            })
+            .map(|(i, name, state, snow)| {
+                let data = snow.frame_data();
+                format!(
+                    "monitor {i} ({}): {}, {}/{} particles, gravity {:?}, {:.1} ms/frame",
+                    name.as_deref().unwrap_or("unnamed"),
+                    match (state.running(), self.controls.monitors[i].1) {
+                        (_, false) => "disabled",
+                        (true, _) => "running",
+                        (false, _) => "paused",
+                    },
+                    snow.active_count(),
+                    snow.particle_count(),
+                    data.gravity,
+                    data.dt * 1000.0,
+                )
+            })
+        .chain(self.power.state().map(|v| format!(
+            "power: {}% on {}, {:?}",
+            v.percent,
+            if v.on_battery { "battery" } else { "ac" },
+            self.power.mode(),
+        )))
+        .collect::<Vec<_>>()
+        .join("\n")
+    }
+
     pub fn event(&mut self, id: &WindowId, event: WindowEvent) {
         if let Some(state) = self.states.get_mut(id) {
             state.event(event);
         } else { tracing::info!("got invalid id for window event") }
     }
 
-    pub fn update(&mut self) {
-        for state in self.states.values_mut() {
-            state.update(&self.queue);
+    pub fn resize(&mut self, id: &WindowId, size: PhysicalSize<u32>) {
+        if let Some(state) = self.states.get_mut(id) {
+            state.resize(&self.device, size);
         }
+        self.update_views();
     }
 
-    pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
-        for state in self.states.values_mut() {
-            state.render(&self.device, &self.queue)?;
+    /// after `SurfaceError::Lost` or `Outdated`
+    pub fn reconfigure(&mut self, id: &WindowId) {
+        if let Some(state) = self.states.get_mut(id) {
+            state.reconfigure(&self.device);
+        }
+    }
+
+    /// recreates the device and everything on it once it was lost,
+    /// e.g. after a gpu reset or waking from sleep. `true` if it did
+    pub fn recover_device(&mut self) -> Result<bool, BuildError> {
+        if !self.device_lost.load(Ordering::Relaxed)
+            || self.last_recovery.is_some_and(|v| v.elapsed() < RECOVERY_INTERVAL)
+        {
+            return Ok(false);
+        }
+        self.last_recovery = Some(Instant::now());
+
+        // the handler of the old device may still fire, so it keeps its flag
+        let (adapter, device, queue, device_lost) = pollster::block_on(request_device(&self.instance))?;
+        self.device_lost = device_lost;
+        if let Some(shared) = &mut self.shared {
+            let config = monitor_config(&self.config, 0, None, &self.controls);
+            let format = shared.snow().format();
+            shared.recreate(&device, &queue, &adapter, format, &config, self.seed);
+        }
+        for (i, (id, name)) in self.monitors.iter().enumerate() {
+            let Some(state) = self.states.get_mut(id) else { continue };
+            let config = monitor_config(&self.config, i, name.as_deref(), &self.controls);
+            state.recreate(
+                &device, &queue, &adapter,
+                &config, self.config.frame.vsync,
+                self.seed.wrapping_add(i as u64),
+                self.shared.as_ref(),
+            );
+        }
+        self.adapter = adapter;
+        self.device = device;
+        self.queue = queue;
+
+        // the reloaded shaders were lost with the pipelines
+        if let Some(watcher) = &mut self.shader_watcher {
+            watcher.reset();
+        }
+        tracing::info!("recovered from a lost device");
+        Ok(true)
+    }
+
+    pub fn update(&mut self, id: &WindowId) {
+        if self.device_lost.load(Ordering::Relaxed) { return }
+
+        match self.config_watcher.as_mut().and_then(|v| v.poll()) {
+            Some(Ok(config)) => self.apply_config(config),
+            Some(Err(e)) => tracing::error!("keeping the old config: {e}"),
+            None => (),
+        }
+        self.reload_shaders();
+
+        if let Some(state) = self.states.get_mut(id) {
+            state.update(&self.device, &self.queue);
+        }
+        // the shared snow steps with the first monitor that is drawing
+        let stepping = self.monitors.iter()
+            .find(|(id, _)| self.states.get(id).is_some_and(|v| v.running()));
+        if let (Some(shared), Some((first, _))) = (&mut self.shared, stepping) {
+            if first == id {
+                shared.update(&self.device, &self.queue);
+                shared.step(&self.device, &self.queue);
+            }
+        }
+    }
+
+    pub fn render(&mut self, id: &WindowId) -> Result<(), wgpu::SurfaceError> {
+        if self.device_lost.load(Ordering::Relaxed) { return Ok(()) }
+        match self.states.get_mut(id) {
+            Some(state) => state.render(&self.device, &self.queue, self.shared.as_ref()),
+            None => Ok(()),
         }
-        Ok(())
     }
 }
 
+/// the adapter and device for the overlays, and a flag that
+/// is set once the device is gone. other errors are logged
+async fn request_device(
+    instance: &wgpu::Instance,
+) -> Result<(wgpu::Adapter, wgpu::Device, wgpu::Queue, Arc<AtomicBool>), BuildError> {
+    let adapter = instance.request_adapter(&wgpu::RequestAdapterOptions {
+        power_preference: wgpu::PowerPreference::default(),
+        compatible_surface: None,
+        force_fallback_adapter: false,
+    }).await.ok_or(BuildError::NoAdapter)?;
+
+    let (device, queue) = adapter.request_device(&wgpu::DeviceDescriptor {
+        // for the particle budget, where there are timestamps
+        features: adapter.features() & wgpu::Features::TIMESTAMP_QUERY,
+        limits: wgpu::Limits::default(),
+        label: Some("render_device"),
+    }, None).await?;
+
+    let lost = Arc::new(AtomicBool::new(false));
+    let flag = lost.clone();
+    device.on_uncaptured_error(Box::new(move |e| {
+        if !is_device_lost(&e) {
+            tracing::error!("wgpu error: {e}");
+        } else if !flag.swap(true, Ordering::Relaxed) {
+            tracing::error!("device lost: {e}");
+        }
+    }));
+    Ok((adapter, device, queue, lost))
+}
+
+/// wgpu only reports a lost device as the cause of the
+/// validation error of whatever was using it
+fn is_device_lost(error: &wgpu::Error) -> bool {
+    let wgpu::Error::Validation { source, .. } = error else { return false };
+    let mut source: Option<&(dyn std::error::Error + 'static)> = Some(source.as_ref());
+    while let Some(e) = source {
+        // the queue errors wrap it transparently, so it isn't a source of its own
+        let lost = matches!(e.downcast_ref(), Some(DeviceError::Lost))
+            || matches!(e.downcast_ref(), Some(QueueSubmitError::Queue(DeviceError::Lost)))
+            || matches!(e.downcast_ref(), Some(QueueWriteError::Queue(DeviceError::Lost)));
+        if lost { return true }
+        source = e.source();
+    }
+    false
+}
+
+/// every simulation, the shared one included
+fn sims_mut<'a>(
+    states: &'a mut HashMap<WindowId, SnowState>,
+    shared: &'a mut Option<Simulation>,
+) -> impl Iterator<Item = &'a mut Simulation> {
+    states.values_mut()
+        .filter_map(|v| v.sim_mut())
+        .chain(shared.as_mut())
+}
+
+/// the config of monitor `i` with the overrides of the controls.
+/// a shared snow only uses the top level keys
+fn monitor_config(config: &Config, i: usize, name: Option<&str>, controls: &Controls) -> SnowConfig {
+    let config = match config.shared {
+        true => &config.snow,
+        false => config.for_monitor(i, name),
+    };
+    let config = match controls.preset {
+        Some(preset) => scaled(&config.with_preset(preset), controls.intensity),
+        None => scaled(config, controls.intensity),
+    };
+    SnowConfig {
+        gravity: controls.gravity.unwrap_or(config.gravity),
+        particle_count: controls.particles.unwrap_or(config.particle_count),
+        ..config
+    }
+}
+
+/// the particle count scaled by the intensity of the controls
+fn scaled(config: &SnowConfig, intensity: f32) -> SnowConfig {
+    let count = (config.particle_count as f32 * intensity).round() as usize;
+    SnowConfig {
+        particle_count: count.clamp(1, MAX_PARTICLES),
+        ..config.clone()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::preset::Preset;
+
+    #[test]
+    fn detects_lost_devices() {
+        let validation = |source: Box<dyn std::error::Error + Send>| wgpu::Error::Validation {
+            description: source.to_string(),
+            source,
+        };
+        assert!(is_device_lost(&validation(Box::new(QueueSubmitError::Queue(DeviceError::Lost)))));
+        assert!(is_device_lost(&validation(Box::new(DeviceError::Lost))));
+        assert!(!is_device_lost(&validation(Box::new(DeviceError::WrongDevice))));
+        assert!(!is_device_lost(&wgpu::Error::OutOfMemory { source: Box::new(DeviceError::OutOfMemory) }));
+    }
+
+    #[test]
+    fn controls_override_the_config() {
+        let config = Config::default();
+        let mut controls = Controls::new([]);
+        controls.apply(Command::SetGravity([0.5, -3.0])).unwrap();
+        controls.apply(Command::SetPreset(Preset::Rain)).unwrap();
+        controls.apply(Command::SetIntensity(2.0)).unwrap();
+
+        // and keep doing so when the config is reloaded
+        let snow = monitor_config(&config, 0, None, &controls);
+        assert_eq!(snow.gravity, [0.5, -3.0]);
+        assert_eq!(snow.style, Preset::Rain.config().style);
+        assert_eq!(snow.particle_count, Preset::Rain.config().particle_count * 2);
+
+        // a set count stays through a reload, until the intensity changes
+        controls.apply(Command::SetParticles(700)).unwrap();
+        let mut reloaded = config;
+        reloaded.snow.particle_count = 300;
+        assert_eq!(monitor_config(&reloaded, 0, None, &controls).particle_count, 700);
+        controls.apply(Command::SetIntensity(0.5)).unwrap();
+        assert_eq!(monitor_config(&reloaded, 0, None, &controls).particle_count, 150);
+    }
+}
@@ -0,0 +1,165 @@
+//! golden image tests for the shaders.
+//!
+//! renders a fixed scene headless and compares frames against the pngs in
+//! `tests/golden`. run with `SNOW_BLESS=1` to (re)write the goldens, on
+//! failure a diff image is written to `target/golden-diff`. like every test
+//! on the gpu they fail without an adapter, unless `SNOW_SKIP_GPU` is set.
+//!
+//! every flake in the scene depends on the order of the rng draws when
+//! spawning and respawning, so keep that order.
+
+use std::path::{Path, PathBuf};
+
+use crate::{
+    config::SnowConfig,
+    headless::{self, Frame, HeadlessState},
+    snow::{RectInstance, SimBackend},
+};
+
+
+const WIDTH: u32 = 512;
+const HEIGHT: u32 = 288;
+const PARTICLES: usize = 500;
+const SEED: u64 = 0x5eed;
+const DT: f32 = 1.0 / 60.0;
+
+/// channel difference that still counts as the same pixel
+const CHANNEL_TOLERANCE: u8 = 8;
+/// fraction of pixels that may differ before the test fails
+const PIXEL_TOLERANCE: f64 = 0.001;
+
+fn golden_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden")
+}
+
+fn diff_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("target/golden-diff")
+}
+
+fn load_png(path: &Path) -> Option<Frame> {
+    let decoder = png::Decoder::new(std::fs::File::open(path).ok()?);
+    let mut reader = decoder.read_info().ok()?;
+    let mut data = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut data).ok()?;
+    assert_eq!(info.color_type, png::ColorType::Rgba, "golden {path:?} is not rgba");
+    data.truncate(info.buffer_size());
+    Some(Frame { width: info.width, height: info.height, data })
+}
+
+fn rect(pos: [f32; 2], dim: [f32; 2], cap: u32) -> RectInstance {
+    RectInstance { pos, dim, cap, _padding: 0 }
+}
+
+fn scene(device: &wgpu::Device, sim: SimBackend) -> HeadlessState {
+    let config = SnowConfig { particle_count: PARTICLES, ..Default::default() };
+    let mut state = HeadlessState::new(device, WIDTH, HEIGHT, &config, SEED, sim);
+    let frame_data = state.snow_mut().frame_data_mut();
+    frame_data.time = 0.0;
+    frame_data.gravity = [0.1, -1.0];
+    frame_data.aspect = WIDTH as f32 / HEIGHT as f32;
+    state
+}
+
+/// marks every pixel outside the tolerance in red on top of a dimmed golden
+fn diff_image(golden: &Frame, actual: &Frame) -> (Frame, usize) {
+    let mut differing = 0;
+    let data = golden.data.chunks(4).zip(actual.data.chunks(4))
+        .flat_map(|(g, a)| {
+            let same = g.iter().zip(a).all(|(g, a)| g.abs_diff(*a) <= CHANNEL_TOLERANCE);
+            if same {
+                let v = g[..3].iter().map(|v| *v as u32).sum::<u32>() / 6;
+                [v as u8, v as u8, v as u8, 255]
+            } else {
+                differing += 1;
+                [255, 0, 0, 255]
+            }
+        })
+    .collect();
+    (Frame { width: golden.width, height: golden.height, data }, differing)
+}
+
+fn assert_golden(name: &str, actual: &Frame) {
+    let path = golden_dir().join(format!("{name}.png"));
+    if std::env::var_os("SNOW_BLESS").is_some() {
+        std::fs::create_dir_all(golden_dir()).unwrap();
+        actual.save_png(&path).unwrap();
+        return;
+    }
+
+    let golden = load_png(&path)
+        .unwrap_or_else(|| panic!("missing golden {path:?}, run with SNOW_BLESS=1"));
+    assert_eq!(
+        (golden.width, golden.height), (actual.width, actual.height),
+        "golden {name} has a different size",
+    );
+
+    let (diff, differing) = diff_image(&golden, actual);
+    let fraction = differing as f64 / (actual.width * actual.height) as f64;
+    if fraction > PIXEL_TOLERANCE {
+        std::fs::create_dir_all(diff_dir()).unwrap();
+        let diff_path = diff_dir().join(format!("{name}.diff.png"));
+        let actual_path = diff_dir().join(format!("{name}.actual.png"));
+        diff.save_png(&diff_path).unwrap();
+        actual.save_png(&actual_path).unwrap();
+        panic!(
+            "{name}: {differing} pixels ({:.3}%) differ from the golden, see {diff_path:?}",
+            fraction * 100.0,
+        );
+    }
+}
+
+#[test]
+fn snowflakes() {
+    let Some((device, queue, sim)) = headless::test_device() else { return };
+    let mut state = scene(&device, sim);
+
+    let frames = (0..60).map(|_| state.render(&device, &queue, DT)).collect::<Vec<_>>();
+    assert_golden("snow_first", &frames[0]);
+    assert_golden("snow_last", &frames[frames.len() - 1]);
+}
+
+#[test]
+fn window_rects() {
+    let Some((device, queue, sim)) = headless::test_device() else { return };
+    let mut state = scene(&device, sim);
+
+    state.snow_mut().set_draw_windows(true);
+    state.snow_mut().write_windows(&queue, &[
+        rect([0.02, 0.02], [0.1, 0.08], 1),
+        rect([0.1, 0.05], [0.12, 0.1], 2),
+        rect([0.0, 0.2], [0.25, 0.05], 3),
+    ]);
+
+    let frame = state.render(&device, &queue, 0.0);
+    assert_golden("window_rects", &frame);
+}
+
+#[test]
+fn snow_caps() {
+    let Some((device, queue, sim)) = headless::test_device() else { return };
+    let mut state = scene(&device, sim);
+
+    state.snow_mut().set_draw_windows(true);
+    state.snow_mut().write_windows(&queue, &[
+        rect([0.1, 0.3], [0.35, 0.4], 1),
+        rect([0.3, 0.5], [0.5, 0.3], 2),
+    ]);
+    // fast enough for a few seconds of snow to settle
+    state.snow_mut().frame_data_mut().gravity = [0.1, -200.0];
+
+    let frame = (0..240).map(|_| state.render(&device, &queue, DT)).last().unwrap();
+    assert_golden("snow_caps", &frame);
+}
+
+#[test]
+fn cpu_fallback() {
+    let Some((device, queue, _)) = headless::test_device() else { return };
+    let mut state = scene(&device, SimBackend::Cpu);
+    let before = state.snow().read_instances(&device, &queue);
+
+    let frames = (0..60).map(|_| state.render(&device, &queue, DT)).collect::<Vec<_>>();
+    let after = state.snow().read_instances(&device, &queue);
+    assert!(before.iter().zip(&after).all(|(a, b)| a.pos != b.pos), "flakes did not move");
+    assert_golden("snow_first", &frames[0]);
+    assert_golden("snow_last", &frames[frames.len() - 1]);
+}
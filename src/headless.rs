@@ -0,0 +1,173 @@
+use std::{io::Write, path::Path};
+
+use crate::{config::SnowConfig, snow::{Snow, BuildError, SimBackend}};
+
+
+pub const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+/// a frame read back from the gpu, tightly packed rgba8 rows
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>,
+}
+
+impl Frame {
+    pub fn write_png(&self, w: impl Write) -> Result<(), png::EncodingError> {
+        let mut encoder = png::Encoder::new(w, self.width, self.height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.write_header()?.write_image_data(&self.data)
+    }
+
+    pub fn save_png(&self, path: impl AsRef<Path>) -> Result<(), png::EncodingError> {
+        let file = std::fs::File::create(path)?;
+        self.write_png(std::io::BufWriter::new(file))
+    }
+}
+
+/// runs the simulation without a window, rendering into an
+/// offscreen texture that gets copied back after every frame
+pub struct HeadlessState {
+    snow: Snow,
+    texture: wgpu::Texture,
+    readback_buffer: wgpu::Buffer,
+    width: u32,
+    height: u32,
+    padded_bytes_per_row: u32,
+}
+
+impl HeadlessState {
+    pub fn new(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        config: &SnowConfig,
+        seed: u64,
+        sim: SimBackend,
+    ) -> Self {
+        let aspect = width as f32 / height as f32;
+        let snow = Snow::new(device, FORMAT, config, aspect, seed, sim);
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("headless target"),
+            size: wgpu::Extent3d { width, height, depth_or_array_layers: 1 },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+
+        // rows of a texture copy have to be aligned
+        let bytes_per_row = width * 4;
+        let padded_bytes_per_row = bytes_per_row.next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+        let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("headless readback"),
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            size: padded_bytes_per_row as u64 * height as u64,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            snow, texture, readback_buffer,
+            width, height, padded_bytes_per_row,
+        }
+    }
+
+    pub fn snow(&self) -> &Snow { &self.snow }
+    pub fn snow_mut(&mut self) -> &mut Snow { &mut self.snow }
+
+    /// steps the simulation by `dt` and returns the rendered frame
+    pub fn render(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        dt: f32,
+    ) -> Frame {
+        let frame_data = self.snow.frame_data_mut();
+        frame_data.dt = dt;
+        frame_data.time += dt;
+        self.snow.prepare(queue);
+
+        let view = self.texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let mut encoder = device.create_command_encoder(
+            &wgpu::CommandEncoderDescriptor {
+                label: Some("headless-encoder"),
+            }
+        );
+
+        self.snow.encode(&mut encoder, &view);
+        encoder.copy_texture_to_buffer(
+            self.texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &self.readback_buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(self.padded_bytes_per_row),
+                    rows_per_image: Some(self.height),
+                },
+            },
+            self.texture.size(),
+        );
+        queue.submit(Some(encoder.finish()));
+
+        let slice = self.readback_buffer.slice(..);
+        slice.map_async(wgpu::MapMode::Read, |res| {
+            if let Err(e) = res { tracing::error!("could not map readback buffer: {e}") }
+        });
+        device.poll(wgpu::Maintain::Wait);
+
+        let bytes_per_row = self.width as usize * 4;
+        let data = slice.get_mapped_range()
+            .chunks(self.padded_bytes_per_row as usize)
+            .flat_map(|row| &row[..bytes_per_row])
+            .copied()
+        .collect();
+        self.readback_buffer.unmap();
+
+        Frame { width: self.width, height: self.height, data }
+    }
+}
+
+/// a device that does not need a surface. with `force_fallback_adapter`
+/// this picks a software rasterizer, which is what ci machines have
+pub async fn request_device(
+    force_fallback_adapter: bool,
+) -> Result<(wgpu::Device, wgpu::Queue, SimBackend), BuildError> {
+    let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+        backends: wgpu::Backends::all(),
+        ..Default::default()
+    });
+
+    let adapter = instance.request_adapter(&wgpu::RequestAdapterOptions {
+        power_preference: wgpu::PowerPreference::default(),
+        compatible_surface: None,
+        force_fallback_adapter,
+    }).await.ok_or(BuildError::NoAdapter)?;
+    tracing::info!("headless adapter: {:?}", adapter.get_info());
+
+    let (device, queue) = adapter.request_device(&wgpu::DeviceDescriptor {
+        features: wgpu::Features::empty(),
+        limits: adapter.limits(),
+        label: Some("headless_device"),
+    }, None).await?;
+
+    Ok((device, queue, SimBackend::for_adapter(&adapter)))
+}
+
+/// the software adapter. panics without one, unless `SNOW_SKIP_GPU`
+/// is set to skip the tests that need it, then it is `None`
+#[cfg(test)]
+pub fn test_device() -> Option<(wgpu::Device, wgpu::Queue, SimBackend)> {
+    match pollster::block_on(request_device(true)) {
+        Ok(v) => Some(v),
+        Err(e) if std::env::var_os("SNOW_SKIP_GPU").is_some() => {
+            tracing::warn!("skipping test, no headless device: {e}");
+            None
+        },
+        Err(e) => panic!("no headless device, set SNOW_SKIP_GPU to skip the tests that need one: {e}"),
+    }
+}
@@ -0,0 +1,187 @@
+//! a unix socket to control a running overlay.
+//!
+//! the protocol is line based, every command gets a reply that ends
+//! with an empty line. replies start with `ok` or `error: `:
+//!
+//! ```text
+//! pause | resume | reload | stats | quit
+//! enable <monitor> | disable <monitor>
+//! set particles <count>
+//! set gravity <x> <y>
+//! set intensity <scale>
+//! set preset <snow|rain|leaves|confetti|cherry_blossoms>
+//! ```
+
+use std::{
+    io::{BufRead, BufReader, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    path::{Path, PathBuf},
+    sync::mpsc,
+    time::Duration,
+};
+
+use crate::command::Command;
+
+
+/// how long a client may take to send a command, and
+/// how long the overlay may take to answer it
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// a command from a client, answered through `reply`
+pub struct Request {
+    pub command: Command,
+    reply: mpsc::Sender<Result<String, String>>,
+}
+
+impl Request {
+    pub fn reply(self, v: Result<String, String>) {
+        // the client might have given up already
+        let _ = self.reply.send(v);
+    }
+}
+
+/// listens on the socket in a background thread, the
+/// requests are handled on the event loop through `poll`
+pub struct Server {
+    path: PathBuf,
+    requests: mpsc::Receiver<Request>,
+}
+
+impl Server {
+    pub fn bind(path: PathBuf) -> std::io::Result<Self> {
+        let listener = match UnixListener::bind(&path) {
+            Err(e) if e.kind() == std::io::ErrorKind::AddrInUse => {
+                if UnixStream::connect(&path).is_ok() {
+                    return Err(e);
+                }
+                // left behind by an overlay that did not shut down
+                std::fs::remove_file(&path)?;
+                UnixListener::bind(&path)?
+            },
+            v => v?,
+        };
+        tracing::info!("listening on {path:?}");
+
+        let (tx, requests) = mpsc::channel();
+        std::thread::Builder::new()
+            .name("ipc".to_string())
+            .spawn(move || {
+                for stream in listener.incoming() {
+                    let result = stream.and_then(|v| serve(v, &tx));
+                    match result {
+                        Ok(true) => (),
+                        // the event loop is gone
+                        Ok(false) => break,
+                        Err(e) => tracing::warn!("ipc client: {e}"),
+                    }
+                }
+            })?;
+
+        Ok(Self { path, requests })
+    }
+
+    pub fn poll(&self) -> impl Iterator<Item = Request> + '_ {
+        self.requests.try_iter()
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// answers every command of a client, `false` once the event loop is gone
+fn serve(stream: UnixStream, requests: &mpsc::Sender<Request>) -> std::io::Result<bool> {
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    let mut writer = &stream;
+    for line in BufReader::new(&stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() { continue }
+
+        let reply = match Command::parse(&line) {
+            Ok(command) => {
+                let (reply, rx) = mpsc::channel();
+                if requests.send(Request { command, reply }).is_err() {
+                    return Ok(false);
+                }
+                rx.recv_timeout(TIMEOUT)
+                    .unwrap_or_else(|_| Err("the overlay did not answer".to_string()))
+            },
+            Err(e) => Err(e),
+        };
+        match reply {
+            Ok(v) if v.is_empty() => writeln!(writer, "ok\n")?,
+            Ok(v) => writeln!(writer, "ok\n{v}\n")?,
+            Err(e) => writeln!(writer, "error: {e}\n")?,
+        }
+    }
+    Ok(true)
+}
+
+/// `$XDG_RUNTIME_DIR/snow.sock`, falling back to the temp dir
+pub fn socket_path() -> PathBuf {
+    match std::env::var_os("XDG_RUNTIME_DIR").filter(|v| !v.is_empty()) {
+        Some(dir) => Path::new(&dir).join("snow.sock"),
+        None => {
+            let user = std::env::var("USER").unwrap_or_default();
+            std::env::temp_dir().join(format!("snow-{user}.sock"))
+        },
+    }
+}
+
+/// sends `command` to the overlay at `path` and returns its reply
+pub fn send(path: &Path, command: &str) -> anyhow::Result<String> {
+    let mut stream = UnixStream::connect(path)
+        .map_err(|e| anyhow::anyhow!("could not connect to {path:?}, is snow running? {e}"))?;
+    stream.set_read_timeout(Some(TIMEOUT * 2))?;
+    writeln!(stream, "{command}")?;
+    stream.shutdown(std::net::Shutdown::Write)?;
+
+    let mut lines = BufReader::new(stream).lines();
+    let status = lines.next().transpose()?.unwrap_or_default();
+    let body = lines.map_while(Result::ok)
+        .take_while(|v| !v.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n");
+    match status.strip_prefix("error: ") {
+        Some(e) => anyhow::bail!("{e}"),
+        None => Ok(body),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let path = std::env::temp_dir().join(format!("snow-test-{}.sock", std::process::id()));
+        let server = Server::bind(path.clone()).unwrap();
+
+        // answers on the "event loop" like the overlay does
+        let overlay = std::thread::spawn(move || {
+            let mut handled = 0;
+            while handled < 2 {
+                for request in server.poll() {
+                    let reply = match request.command {
+                        Command::Stats => Ok("monitor 0: running".to_string()),
+                        Command::SetParticles(_) => Err("no monitors".to_string()),
+                        _ => Ok(String::new()),
+                    };
+                    request.reply(reply);
+                    handled += 1;
+                }
+                std::thread::sleep(Duration::from_millis(1));
+            }
+        });
+
+        assert_eq!(send(&path, "stats").unwrap(), "monitor 0: running");
+        assert_eq!(send(&path, "set particles 10").unwrap_err().to_string(), "no monitors");
+        // rejected before it reaches the overlay
+        assert!(send(&path, "set particles").is_err());
+        overlay.join().unwrap();
+        // the socket is removed with the server
+        assert!(!path.exists());
+    }
+}
@@ -1,118 +1,100 @@
 #![allow(unused)]
 
-use objc2::rc::Id;
-use raw_window_handle::{HasRawWindowHandle, RawWindowHandle};
+use std::path::PathBuf;
+
 use winit::{
     event_loop::{EventLoopBuilder, ControlFlow},
-    window::{WindowBuilder, WindowLevel},
     event::{Event, WindowEvent},
-    platform::macos::EventLoopBuilderExtMacOS, dpi::{LogicalPosition, PhysicalPosition},
 };
 use tracing_subscriber::prelude::*;
 
-use icrate::{
-    Foundation::{MainThreadMarker, ns_string},
-    AppKit::{NSApplication, NSStatusBar, NSImage, NSView, NSScreen, NSWindow, self}
-};
+use command::Command;
+use config::Config;
 
+mod app;
+mod budget;
+mod command;
+mod config;
+mod cpu;
+mod cursor;
 mod gfx;
+#[cfg(test)]
+mod golden;
+mod headless;
+mod ipc;
+mod pacing;
+mod platform;
+mod power;
+mod preset;
+mod shader;
 mod snow;
+mod sprites;
 mod utils;
+mod wind;
+mod windows;
 
 fn main() -> anyhow::Result<()> {
     tracing_subscriber::fmt()
         .with_env_filter(tracing_subscriber::EnvFilter::from_env("wgpu=warn"))
     .init();
 
-    let event_loop = EventLoopBuilder::new()
-    .build()?;
-
-    let main_thread = MainThreadMarker::new().expect("not on main thread");
-    let app = NSApplication::sharedApplication(main_thread);
-    let main_screen = NSScreen::mainScreen(main_thread).expect("no main screen");
-    
-    let win2 = WindowBuilder::new()
-        .with_transparent(true)
-        .with_active(false)
-        .with_decorations(false)
-        // .with_window_level(WindowLevel::AlwaysOnBottom)
-        .with_window_level(WindowLevel::AlwaysOnTop)
-    .build(&event_loop)?;
-
-    // let win2 = WindowBuilder::new()
-    //     // .with_transparent(true)
-    //     // .with_blur(true)
-    //     .with_active(false)
-    //     .with_decorations(false)
-    //     .with_window_level(WindowLevel::AlwaysOnTop)
-    // .build(&event_loop)?;
-
-    let win2_raw = win2.raw_window_handle();
-    let win2_nsview: Id<NSView> = match win2_raw {
-        RawWindowHandle::AppKit(handle) => unsafe {
-            Id::new(handle.ns_view as *mut NSView)
-                .expect("could not get ns_view")
-        },
-        _ => panic!("did not get the appropriate window handle"),
+    let mut args = std::env::args().skip(1).collect::<Vec<_>>();
+    let seed = match take_option(&mut args, "--seed") {
+        Some(v) => v.parse()?,
+        None => rand::random(),
     };
-    let win2_nswindow = win2_nsview.window().expect("could not get window");
-    win2_nswindow.setTitle(ns_string!("test"));
-
-    win2_nswindow.setMovable(false);
-    win2_nswindow.setFrame_display(main_screen.frame(), true);
-    unsafe {
-        win2_nswindow.setCollectionBehavior(
-            // moves with current space + can overlay fullscreen windows
-              AppKit::NSWindowCollectionBehaviorCanJoinAllSpaces
-            // cant tab to window
-            | AppKit::NSWindowCollectionBehaviorIgnoresCycle
-            // stays even while mission control
-            | AppKit::NSWindowCollectionBehaviorStationary
-            // dont show in fullscreen
-            | AppKit::NSWindowCollectionBehaviorFullScreenNone
-        );
+    tracing::info!("seed: {seed}");
+    let config_path = take_option(&mut args, "--config").map(PathBuf::from);
+    // reloads the shaders from this directory while working on them
+    let shader_dir = take_option(&mut args, "--shaders").map(PathBuf::from);
+
+    let mut args = args.into_iter();
+    let cmd = args.next();
+    // only talks to the socket, so a broken config is no reason to fail
+    if cmd.as_deref() == Some("ctl") {
+        return ctl(args);
     }
-    win2.set_cursor_hittest(false)?;
-
-    unsafe {
-        let status_bar = NSStatusBar::systemStatusBar();
-        let status_item = status_bar.statusItemWithLength(AppKit::NSSquareStatusItemLength);
-        if let Some(btn) = status_item.button(main_thread) {
-            btn.setImage(NSImage::imageWithSystemSymbolName_accessibilityDescription(
-                ns_string!("mic"),
-                None,
-            ).as_deref());
-        }
-
-        if let Some(btn) = status_item.button(main_thread) {
-            btn.setImage(NSImage::imageWithSystemSymbolName_accessibilityDescription(
-                ns_string!("snowflake"),
-                None,
-            ).as_deref());
-        }
+    let config = Config::load(config_path.as_deref())?;
+    match cmd.as_deref() {
+        Some("headless") => return headless(args, seed, &config),
+        Some(cmd) => anyhow::bail!("unknown command: {cmd}"),
+        None => (),
     }
 
+    let event_loop = EventLoopBuilder::new()
+    .build()?;
+
+    let mut app = app::init(&event_loop)?;
+
     let mut state = pollster::block_on(
-        gfx::State::new(main_thread, &event_loop)
+        gfx::State::new(&event_loop, seed, &config)
     )?;
+    if let Some(path) = config_path.or_else(config::default_path) {
+        state.watch_config(path);
+    }
+    if let Some(dir) = shader_dir {
+        state.watch_shaders(dir);
+    }
+    app.set_menu(&state.menu());
 
-    event_loop.set_control_flow(ControlFlow::Poll);
-
-    // add:
-    // - collision field (land on windows)
-    // - velocity field (interaction with cursor)
+    let ipc = ipc::Server::bind(ipc::socket_path())
+        .inspect_err(|e| tracing::warn!("no control socket: {e}"))
+    .ok();
 
-    event_loop.set_control_flow(ControlFlow::Poll);
+    event_loop.set_control_flow(state.control_flow());
 
     event_loop.run(move |ev, target| {
         match ev {
             Event::WindowEvent { event, window_id } => match event {
                 WindowEvent::CloseRequested => target.exit(),
+                WindowEvent::Resized(size) => state.resize(&window_id, size),
                 WindowEvent::RedrawRequested => {
-                    state.update();
-                    match state.render() {
+                    state.update(&window_id);
+                    match state.render(&window_id) {
                         Ok(_) => (),
-                        // Err(wgpu::SurfaceError::Lost) => state.resize(state.size),
+                        Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => {
+                            state.reconfigure(&window_id);
+                        },
                         Err(wgpu::SurfaceError::OutOfMemory) => {
                             tracing::error!("out of memory");
                             target.exit();
@@ -124,10 +106,82 @@ fn main() -> anyhow::Result<()> {
                 event => state.event(&window_id, event),
                 _ => (),
             },
-            Event::AboutToWait => state.redraw(),
+            Event::AboutToWait => {
+                let mut handled = false;
+                for request in ipc.iter().flat_map(|v| v.poll()) {
+                    handled = true;
+                    if request.command == Command::Quit {
+                        request.reply(Ok(String::new()));
+                        target.exit();
+                        continue;
+                    }
+                    let reply = state.handle(request.command);
+                    request.reply(reply);
+                }
+                for command in app.commands() {
+                    handled = true;
+                    if command == Command::Quit {
+                        target.exit();
+                    } else if let Err(e) = state.handle(command) {
+                        tracing::error!("{command:?}: {e}");
+                    }
+                }
+                if let Err(e) = state.recover_device() {
+                    tracing::error!("could not recover the device: {e}");
+                }
+                match state.poll_monitors(target) {
+                    Ok(changed) => handled |= changed,
+                    Err(e) => tracing::error!("could not add a monitor: {e}"),
+                }
+                handled |= state.poll_power();
+                // the menu shows the state the commands changed
+                if handled {
+                    app.set_menu(&state.menu());
+                }
+                state.redraw();
+                target.set_control_flow(state.control_flow());
+            },
             _ => (),
         }
     })?;
     Ok(())
 }
 
+/// removes `name <value>` from `args` and returns the value
+fn take_option(args: &mut Vec<String>, name: &str) -> Option<String> {
+    let i = args.iter().position(|v| v == name)?;
+    args.remove(i);
+    (i < args.len()).then(|| args.remove(i))
+}
+
+/// `ctl <command>`: sends a command to the running overlay, see `ipc`
+fn ctl(args: impl Iterator<Item = String>) -> anyhow::Result<()> {
+    let command = args.collect::<Vec<_>>().join(" ");
+    let reply = ipc::send(&ipc::socket_path(), &command)?;
+    if !reply.is_empty() {
+        println!("{reply}");
+    }
+    Ok(())
+}
+
+/// `headless [frames] [dir]`: renders on a software adapter
+/// and writes every frame into `dir` as a png
+fn headless(
+    mut args: impl Iterator<Item = String>,
+    seed: u64,
+    config: &Config,
+) -> anyhow::Result<()> {
+    let frames: usize = args.next().map(|v| v.parse()).transpose()?.unwrap_or(60);
+    let out = PathBuf::from(args.next().unwrap_or_else(|| "frames".to_string()));
+    std::fs::create_dir_all(&out)?;
+
+    let (device, queue, sim) = pollster::block_on(headless::request_device(true))?;
+    let mut state = headless::HeadlessState::new(&device, 1280, 720, config.for_monitor(0, None), seed, sim);
+    state.snow_mut().load_sprites(&device, &queue, config.for_monitor(0, None))?;
+    for i in 0..frames {
+        let frame = state.render(&device, &queue, 1.0 / 60.0);
+        frame.save_png(out.join(format!("{i:04}.png")))?;
+    }
+    tracing::info!("wrote {frames} frames to {}", out.display());
+    Ok(())
+}
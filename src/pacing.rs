@@ -0,0 +1,158 @@
+//! when frames are drawn: the event loop sleeps until the next frame is
+//! due instead of polling, and the simulation gets a time step that
+//! doesn't jump after a stall
+
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+
+use crate::config::ConfigError;
+
+
+/// when the monitors don't report a refresh rate
+pub const DEFAULT_FPS: f32 = 60.0;
+/// the longest step the simulation takes, in seconds
+const MAX_DT: f32 = 0.1;
+/// how quickly the step follows a change of the frame time
+const SMOOTHING: f32 = 0.25;
+
+/// the `[frame]` table of the config
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FrameConfig {
+    /// frames per second, the fastest monitor's refresh rate without it
+    pub fps: Option<f32>,
+    /// presents in sync with the monitor, without tearing
+    pub vsync: bool,
+}
+
+impl Default for FrameConfig {
+    fn default() -> Self {
+        Self { fps: None, vsync: true }
+    }
+}
+
+impl FrameConfig {
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.fps.is_some_and(|v| !(v > 0.0 && v <= 1000.0)) {
+            return Err(ConfigError::Invalid { key: "fps", reason: "has to be in (0, 1000]" });
+        }
+        Ok(())
+    }
+}
+
+/// the first of the preferred modes the surface supports, fifo is always there
+pub fn present_mode(vsync: bool, available: &[wgpu::PresentMode]) -> wgpu::PresentMode {
+    use wgpu::PresentMode::*;
+    let preferred: &[_] = if vsync { &[Fifo] } else { &[Mailbox, Immediate] };
+    preferred.iter()
+        .copied()
+        .find(|v| available.contains(v))
+    .unwrap_or(Fifo)
+}
+
+/// schedules frames at a fixed interval
+#[derive(Debug, Clone)]
+pub struct FramePacer {
+    interval: Duration,
+    next: Instant,
+}
+
+impl FramePacer {
+    /// the first frame is due right away
+    pub fn new(interval: Duration) -> Self {
+        Self { interval, next: Instant::now() }
+    }
+
+    pub fn interval(&self) -> Duration { self.interval }
+    pub fn next_frame(&self) -> Instant { self.next }
+
+    pub fn set_interval(&mut self, interval: Duration) {
+        if interval == self.interval { return }
+        tracing::info!("frame interval: {interval:?}");
+        self.next = self.next - self.interval + interval;
+        self.interval = interval;
+    }
+
+    /// `true` if a frame is due at `now`, which then schedules the next one
+    pub fn frame_due(&mut self, now: Instant) -> bool {
+        if now < self.next {
+            return false;
+        }
+        self.next += self.interval;
+        // too far behind to catch up, e.g. after a stall
+        if self.next <= now {
+            self.next = now + self.interval;
+        }
+        true
+    }
+}
+
+/// turns the time between frames into the step of the simulation
+#[derive(Debug, Clone, Default)]
+pub struct FrameClock {
+    dt: Option<f32>,
+}
+
+impl FrameClock {
+    /// clamps and smooths `elapsed` seconds
+    pub fn step(&mut self, elapsed: f32) -> f32 {
+        let elapsed = elapsed.clamp(0.0, MAX_DT);
+        let dt = self.dt.map_or(elapsed, |dt| dt + (elapsed - dt) * SMOOTHING);
+        self.dt = Some(dt);
+        dt
+    }
+
+    /// starts over after a pause, the frame times before don't matter
+    pub fn reset(&mut self) {
+        self.dt = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paces_frames() {
+        let interval = Duration::from_millis(10);
+        let start = Instant::now();
+        let mut pacer = FramePacer { interval, next: start };
+        assert!(pacer.frame_due(start));
+        assert!(!pacer.frame_due(start + Duration::from_millis(5)));
+        // a late frame doesn't push the ones after it back
+        assert!(pacer.frame_due(start + Duration::from_millis(12)));
+        assert_eq!(pacer.next_frame(), start + Duration::from_millis(20));
+
+        // but a stall is not made up for
+        assert!(pacer.frame_due(start + Duration::from_millis(100)));
+        assert_eq!(pacer.next_frame(), start + Duration::from_millis(110));
+
+        pacer.set_interval(Duration::from_millis(20));
+        assert_eq!(pacer.next_frame(), start + Duration::from_millis(120));
+    }
+
+    #[test]
+    fn clamps_and_smooths_steps() {
+        let mut clock = FrameClock::default();
+        assert_eq!(clock.step(0.016), 0.016);
+        let after_stall = clock.step(2.0);
+        assert!(after_stall > 0.016 && after_stall < MAX_DT);
+        for _ in 0..100 {
+            clock.step(5.0);
+        }
+        assert!(clock.step(5.0) <= MAX_DT);
+
+        clock.reset();
+        assert_eq!(clock.step(0.008), 0.008);
+    }
+
+    #[test]
+    fn picks_present_modes() {
+        use wgpu::PresentMode::*;
+        assert_eq!(present_mode(true, &[Immediate, Mailbox, Fifo]), Fifo);
+        assert_eq!(present_mode(false, &[Fifo, Immediate, Mailbox]), Mailbox);
+        assert_eq!(present_mode(false, &[Fifo, Immediate]), Immediate);
+        assert_eq!(present_mode(false, &[Fifo]), Fifo);
+    }
+}
@@ -0,0 +1,59 @@
+use winit::{event_loop::EventLoopWindowTarget, monitor::MonitorHandle, window::Window};
+
+use crate::{
+    cursor::{CursorSource, NoCursor},
+    power::{NoBattery, PowerSource},
+    windows::{ScriptedWindows, WindowSource},
+};
+
+
+pub type Monitor = MonitorHandle;
+
+pub fn monitors<E>(event_loop: &EventLoopWindowTarget<E>) -> Vec<Monitor> {
+    event_loop.available_monitors().collect()
+}
+
+pub fn same_monitor(a: &Monitor, b: &Monitor) -> bool {
+    a == b
+}
+
+/// position and size, to notice resolution changes
+pub fn monitor_frame(monitor: &Monitor) -> [f64; 4] {
+    let (pos, size) = (monitor.position(), monitor.size());
+    [pos.x as f64, pos.y as f64, size.width as f64, size.height as f64]
+}
+
+pub fn monitor_name(monitor: &Monitor) -> Option<String> {
+    monitor.name()
+}
+
+/// in hertz, if the monitor reports it
+pub fn refresh_rate(monitor: &Monitor) -> Option<f32> {
+    monitor.refresh_rate_millihertz().map(|v| v as f32 / 1000.0)
+}
+
+/// only what winit can do on its own, the window will not
+/// be excluded from any window manager features
+pub fn configure_window(window: &Window, monitor: &Monitor) {
+    window.set_outer_position(monitor.position());
+    let _ = window.request_inner_size(monitor.size());
+}
+
+pub fn window_scale(_window: &Window) -> f64 {
+    1.0
+}
+
+/// there is no way to list windows here, so snow never sees any
+pub fn window_source() -> Box<dyn WindowSource> {
+    Box::new(ScriptedWindows::default())
+}
+
+/// winit only knows about the cursor while it is over one of our windows,
+/// which the overlay never is
+pub fn cursor_source() -> Box<dyn CursorSource> {
+    Box::new(NoCursor)
+}
+
+pub fn power_source() -> Box<dyn PowerSource> {
+    Box::new(NoBattery)
+}
@@ -0,0 +1,202 @@
+use icrate::{
+    AppKit::{NSScreen, NSView, self},
+    Foundation::{MainThreadMarker, NSDictionary, NSNumber, NSString, ns_string},
+};
+use objc2::{
+    rc::{Id, autoreleasepool},
+    runtime::NSObject,
+    Message,
+};
+use raw_window_handle::{HasRawWindowHandle, RawWindowHandle};
+use winit::{event_loop::EventLoopWindowTarget, window::Window};
+
+use crate::{
+    cursor::CursorSource,
+    power::{PowerSource, PowerState},
+    windows::{AppWindow, WindowSource},
+};
+
+
+pub type Monitor = Id<NSScreen>;
+
+pub fn monitors<E>(_event_loop: &EventLoopWindowTarget<E>) -> Vec<Monitor> {
+    let main_thread = MainThreadMarker::new().expect("not on main thread");
+    NSScreen::screens(main_thread).into_iter().collect()
+}
+
+pub fn monitor_name(monitor: &Monitor) -> Option<String> {
+    Some(unsafe { monitor.localizedName() }.to_string())
+}
+
+/// in hertz, 0 before macos 12
+pub fn refresh_rate(monitor: &Monitor) -> Option<f32> {
+    let fps = unsafe { monitor.maximumFramesPerSecond() };
+    (fps > 0).then_some(fps as f32)
+}
+
+/// the `CGDirectDisplayID`, the `NSScreen`s themselves are recreated
+/// whenever the screen parameters change
+fn display_id(monitor: &Monitor) -> Option<u32> {
+    let description = monitor.deviceDescription();
+    let number = description.get(ns_string!("NSScreenNumber"))?;
+    let number: &NSNumber = unsafe { std::mem::transmute(number) };
+    Some(number.as_u32())
+}
+
+pub fn same_monitor(a: &Monitor, b: &Monitor) -> bool {
+    display_id(a).is_some() && display_id(a) == display_id(b)
+}
+
+/// position and size, to notice resolution changes
+pub fn monitor_frame(monitor: &Monitor) -> [f64; 4] {
+    let frame = monitor.frame();
+    [frame.origin.x, frame.origin.y, frame.size.width, frame.size.height]
+}
+
+pub(crate) fn ns_view(window: &Window) -> Id<NSView> {
+    match window.raw_window_handle() {
+        RawWindowHandle::AppKit(handle) => unsafe {
+            Id::new(handle.ns_view as *mut NSView)
+                .expect("could not get ns_view")
+        },
+        v => panic!("invalid window handle type: {v:?}"),
+    }
+}
+
+pub fn configure_window(window: &Window, monitor: &Monitor) {
+    let ns_view = ns_view(window);
+    let ns_window = ns_view.window().expect("could not get ns_window");
+
+    ns_window.setMovable(false);
+    ns_window.setFrame_display(monitor.frame(), true);
+    // disable window shadow to remove artifacts
+    ns_window.setHasShadow(false);
+    // ns_window.setLevel(99999);
+    // ns_window.setLevel(-1);
+
+    unsafe {
+        ns_window.setCollectionBehavior(
+            0
+            // moves with current space + can overlay fullscreen windows
+            |  AppKit::NSWindowCollectionBehaviorCanJoinAllSpaces
+            // cant tab to window
+            | AppKit::NSWindowCollectionBehaviorIgnoresCycle
+            // stays even while mission control
+            | AppKit::NSWindowCollectionBehaviorStationary
+            // dont show in fullscreen
+            | AppKit::NSWindowCollectionBehaviorFullScreenNone
+        );
+    };
+
+    std::mem::forget(ns_view);
+}
+
+/// cg reports windows in points
+pub fn window_scale(window: &Window) -> f64 {
+    window.scale_factor()
+}
+
+pub fn window_source() -> Box<dyn WindowSource> {
+    Box::new(CoreGraphicsWindows)
+}
+
+pub fn cursor_source() -> Box<dyn CursorSource> {
+    Box::new(CoreGraphicsCursor)
+}
+
+pub fn power_source() -> Box<dyn PowerSource> {
+    Box::new(PmsetPower)
+}
+
+/// asks `pmset -g batt`, which prints something like
+///
+/// ```text
+/// Now drawing from 'Battery Power'
+///  -InternalBattery-0 (id=1234)	42%; discharging; 3:12 remaining present: true
+/// ```
+pub struct PmsetPower;
+
+impl PowerSource for PmsetPower {
+    fn state(&mut self) -> Option<PowerState> {
+        let output = std::process::Command::new("pmset").args(["-g", "batt"]).output()
+            .inspect_err(|e| tracing::warn!("could not run pmset: {e}"))
+            .ok()?;
+        let output = String::from_utf8_lossy(&output.stdout);
+        let percent = output.split_whitespace()
+            .find_map(|v| v.strip_suffix("%;")?.parse().ok())?;
+        Some(PowerState {
+            on_battery: output.contains("'Battery Power'"),
+            percent,
+        })
+    }
+}
+
+/// reads the cursor location from an empty `CGEvent`, which
+/// uses the same top left origin and points as the window list
+pub struct CoreGraphicsCursor;
+
+impl CursorSource for CoreGraphicsCursor {
+    fn position(&mut self) -> Option<(f64, f64)> {
+        use core_graphics::{event::CGEvent, event_source::{CGEventSource, CGEventSourceStateID}};
+        let source = CGEventSource::new(CGEventSourceStateID::CombinedSessionState).ok()?;
+        let location = CGEvent::new(source).ok()?.location();
+        Some((location.x, location.y))
+    }
+}
+
+/// lists the on screen windows via `CGWindowListCopyWindowInfo`
+pub struct CoreGraphicsWindows;
+
+impl WindowSource for CoreGraphicsWindows {
+    fn windows(&mut self) -> Vec<AppWindow> {
+        use core_graphics::window;
+        let arr = unsafe {
+            cf_array::<NSDictionary<NSString, NSObject>>(window::CGWindowListCopyWindowInfo(window::kCGWindowListOptionOnScreenOnly, 0))
+        };
+
+        arr.into_iter().map(|v| {
+            autoreleasepool(|p| {
+                let owner_name = v.get(ns_string!("kCGWindowOwnerName")).map(|v| {
+                    let v: &NSString = unsafe { std::mem::transmute(v) };
+                    v.as_str(p).to_string()
+                });
+                let name = v.get(ns_string!("kCGWindowName")).map(|v| {
+                    let v: &NSString = unsafe { std::mem::transmute(v) };
+                    v.as_str(p).to_string()
+                });
+                let bounds: &NSDictionary<NSString, NSNumber> = unsafe {
+                    std::mem::transmute(v.get(ns_string!("kCGWindowBounds")).unwrap())
+                };
+                let (x, y, w, h) = (
+                    bounds[ns_string!("X")].as_f64(),
+                    bounds[ns_string!("Y")].as_f64(),
+                    bounds[ns_string!("Width")].as_f64(),
+                    bounds[ns_string!("Height")].as_f64(),
+                );
+                let layer: &NSNumber = unsafe {
+                    std::mem::transmute(v.get(ns_string!("kCGWindowLayer")).unwrap())
+                };
+                let layer = layer.as_i64();
+                let number: &NSNumber = unsafe {
+                    std::mem::transmute(v.get(ns_string!("kCGWindowNumber")).unwrap())
+                };
+                let number = number.as_i64();
+                AppWindow {
+                    owner_name, layer, name,
+                    dim: (w, h),
+                    pos: (x, y),
+                    number,
+                }
+            })
+        }).collect()
+    }
+}
+
+unsafe fn cf_array<T: Message>(array: core_graphics::display::CFArrayRef) -> Vec<Id<T>> {
+    (0..core_graphics::display::CFArrayGetCount(array)).flat_map(|i| {
+        let unmanaged = core_graphics::display::CFArrayGetValueAtIndex(array, i);
+        if unmanaged.is_null() { tracing::warn!("got null from cf_array") }
+        let rec = std::mem::transmute(unmanaged);
+        Id::new(rec)
+    }).collect()
+}
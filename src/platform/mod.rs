@@ -0,0 +1,31 @@
+//! platform specific parts of the overlay.
+//!
+//! every backend exposes the same items:
+//! - `Monitor`: a handle to a physical screen
+//! - `monitors`: all screens that should get an overlay
+//! - `monitor_name`: a name to pick a monitor by in the config
+//! - `refresh_rate`: to pace the frames by
+//! - `same_monitor` and `monitor_frame`: to notice screen configuration changes
+//! - `configure_window`: turns a winit window into a click-through overlay
+//! - `window_source`: the windows of other applications
+//! - `cursor_source`: the cursor, wherever it is on screen
+//! - `power_source`: whether we are on battery
+//! - `window_scale`: physical pixels per unit of `AppWindow` coordinates
+//!
+//! the app itself and its status bar menu are set up in `app`
+
+#[cfg(target_os = "macos")]
+mod macos;
+#[cfg(target_os = "macos")]
+pub use macos::*;
+
+#[cfg(target_os = "linux")]
+mod x11;
+#[cfg(target_os = "linux")]
+pub use x11::*;
+
+// winit only, also the base of the other non macos backends
+#[cfg(not(target_os = "macos"))]
+mod generic;
+#[cfg(not(any(target_os = "macos", target_os = "linux")))]
+pub use generic::*;
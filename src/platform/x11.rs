@@ -0,0 +1,354 @@
+use std::sync::OnceLock;
+
+use raw_window_handle::{HasRawWindowHandle, RawWindowHandle};
+use winit::window::Window;
+use x11rb::{
+    connection::Connection,
+    errors::{ConnectError, ConnectionError, ReplyError},
+    protocol::xproto::{self, AtomEnum, ClientMessageEvent, ConnectionExt, EventMask, MapState, PropMode},
+    rust_connection::RustConnection,
+    wrapper::ConnectionExt as _,
+};
+
+use crate::{
+    cursor::{CursorSource, NoCursor},
+    power::{PowerSource, SysfsPower},
+    windows::{AppWindow, ScriptedWindows, WindowSource},
+};
+
+use super::generic;
+pub use super::generic::{
+    Monitor, monitor_frame, monitor_name, monitors, refresh_rate, same_monitor, window_scale,
+};
+
+
+// layers of the matching CGWindowLevel keys, so `layer == 0`
+// keeps meaning "normal application window" on every platform
+const FLOATING_LAYER: i64 = 3;
+const DOCK_LAYER: i64 = 20;
+
+// _NET_WM_STATE actions
+const NET_WM_STATE_ADD: u32 = 1;
+
+x11rb::atom_manager! {
+    Atoms: AtomsCookie {
+        _NET_CLIENT_LIST_STACKING,
+        _NET_FRAME_EXTENTS,
+        _NET_WM_NAME,
+        _NET_WM_DESKTOP,
+        _NET_WM_STATE,
+        _NET_WM_STATE_ABOVE,
+        _NET_WM_STATE_HIDDEN,
+        _NET_WM_STATE_STICKY,
+        _NET_WM_STATE_SKIP_TASKBAR,
+        _NET_WM_STATE_SKIP_PAGER,
+        _NET_WM_WINDOW_TYPE,
+        _NET_WM_WINDOW_TYPE_DOCK,
+        UTF8_STRING,
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum X11Error {
+    #[error(transparent)]
+    Connect(#[from] ConnectError),
+
+    #[error(transparent)]
+    Connection(#[from] ConnectionError),
+
+    #[error(transparent)]
+    Reply(#[from] ReplyError),
+}
+
+/// the backend's one connection, opened on first use and shared by
+/// the window list, the cursor and the overlay windows
+fn x11() -> Result<&'static X11, &'static X11Error> {
+    static CONNECTION: OnceLock<Result<X11, X11Error>> = OnceLock::new();
+    CONNECTION.get_or_init(X11::connect).as_ref()
+}
+
+/// a connection to the x server, separate from the one winit uses
+struct X11 {
+    conn: RustConnection,
+    root: xproto::Window,
+    atoms: Atoms,
+}
+
+impl X11 {
+    fn connect() -> Result<Self, X11Error> {
+        let (conn, screen) = x11rb::connect(None)?;
+        let root = conn.setup().roots[screen].root;
+        let atoms = Atoms::new(&conn)?.reply()?;
+        Ok(Self { conn, root, atoms })
+    }
+
+    fn property32(
+        &self,
+        window: xproto::Window,
+        property: xproto::Atom,
+        ty: impl Into<xproto::Atom>,
+    ) -> Result<Vec<u32>, X11Error> {
+        let reply = self.conn.get_property(false, window, property, ty, 0, u32::MAX)?.reply()?;
+        Ok(reply.value32().map(|v| v.collect()).unwrap_or_default())
+    }
+
+    fn property_string(
+        &self,
+        window: xproto::Window,
+        property: xproto::Atom,
+        ty: impl Into<xproto::Atom>,
+    ) -> Result<Option<String>, X11Error> {
+        let reply = self.conn.get_property(false, window, property, ty, 0, u32::MAX)?.reply()?;
+        if reply.value.is_empty() { return Ok(None) }
+        Ok(Some(String::from_utf8_lossy(&reply.value).into_owned()))
+    }
+
+    /// sends a client message to the root window,
+    /// the way the ewmh spec wants state changes of mapped windows
+    fn send_root_message(
+        &self,
+        window: xproto::Window,
+        ty: xproto::Atom,
+        data: [u32; 5],
+    ) -> Result<(), X11Error> {
+        let event = ClientMessageEvent::new(32, window, ty, data);
+        self.conn.send_event(
+            false, self.root,
+            EventMask::SUBSTRUCTURE_REDIRECT | EventMask::SUBSTRUCTURE_NOTIFY,
+            event,
+        )?;
+        Ok(())
+    }
+
+    fn configure_overlay(&self, window: xproto::Window) -> Result<(), X11Error> {
+        let a = &self.atoms;
+        let states = [
+            // stays above normal windows, on every workspace
+            (a._NET_WM_STATE_ABOVE, a._NET_WM_STATE_STICKY),
+            // not listed in taskbars or pagers
+            (a._NET_WM_STATE_SKIP_TASKBAR, a._NET_WM_STATE_SKIP_PAGER),
+        ];
+
+        // the window manager ignores messages about windows it hasn't mapped
+        // yet and reads the properties when it does, so set those as well
+        let attributes = self.conn.get_window_attributes(window)?.reply()?;
+        if attributes.map_state == MapState::UNMAPPED {
+            let mut state = self.property32(window, a._NET_WM_STATE, AtomEnum::ATOM)?;
+            for atom in states.iter().flat_map(|&(first, second)| [first, second]) {
+                if !state.contains(&atom) { state.push(atom) }
+            }
+            self.conn.change_property32(PropMode::REPLACE, window, a._NET_WM_STATE, AtomEnum::ATOM, &state)?;
+            self.conn.change_property32(PropMode::REPLACE, window, a._NET_WM_DESKTOP, AtomEnum::CARDINAL, &[u32::MAX])?;
+        }
+
+        for (first, second) in states {
+            self.send_root_message(
+                window, a._NET_WM_STATE,
+                [NET_WM_STATE_ADD, first, second, 1, 0],
+            )?;
+        }
+        // some window managers only look at the desktop for stickyness
+        self.send_root_message(window, a._NET_WM_DESKTOP, [u32::MAX, 1, 0, 0, 0])?;
+        self.conn.flush()?;
+        Ok(())
+    }
+
+    fn app_window(&self, window: xproto::Window) -> Result<Option<AppWindow>, X11Error> {
+        let a = &self.atoms;
+
+        let attributes = self.conn.get_window_attributes(window)?.reply()?;
+        if attributes.map_state != MapState::VIEWABLE { return Ok(None) }
+
+        let state = self.property32(window, a._NET_WM_STATE, AtomEnum::ATOM)?;
+        if state.contains(&a._NET_WM_STATE_HIDDEN) { return Ok(None) }
+        let types = self.property32(window, a._NET_WM_WINDOW_TYPE, AtomEnum::ATOM)?;
+
+        let layer = if types.contains(&a._NET_WM_WINDOW_TYPE_DOCK) {
+            DOCK_LAYER
+        } else if state.contains(&a._NET_WM_STATE_ABOVE) {
+            FLOATING_LAYER
+        } else { 0 };
+
+        let geometry = self.conn.get_geometry(window)?.reply()?;
+        let origin = self.conn.translate_coordinates(window, self.root, 0, 0)?.reply()?;
+        // include the decorations, cg window bounds contain the title bar as well
+        let (left, right, top, bottom) = match self.property32(window, a._NET_FRAME_EXTENTS, AtomEnum::CARDINAL)?[..] {
+            [left, right, top, bottom] => (left as f64, right as f64, top as f64, bottom as f64),
+            _ => (0.0, 0.0, 0.0, 0.0),
+        };
+
+        let name = match self.property_string(window, a._NET_WM_NAME, a.UTF8_STRING)? {
+            Some(name) => Some(name),
+            None => self.property_string(window, AtomEnum::WM_NAME.into(), AtomEnum::STRING)?,
+        };
+        // WM_CLASS is "instance\0class\0", the class is the application name
+        let owner_name = self.property_string(window, AtomEnum::WM_CLASS.into(), AtomEnum::STRING)?
+            .and_then(|v| v.split('\0').nth(1).map(str::to_string));
+
+        Ok(Some(AppWindow {
+            owner_name, name, layer,
+            pos: (origin.dst_x as f64 - left, origin.dst_y as f64 - top),
+            dim: (
+                geometry.width as f64 + left + right,
+                geometry.height as f64 + top + bottom,
+            ),
+            number: window as i64,
+        }))
+    }
+
+    fn pointer(&self) -> Result<Option<(f64, f64)>, X11Error> {
+        let reply = self.conn.query_pointer(self.root)?.reply()?;
+        // false when the pointer is on another screen of the display
+        Ok(reply.same_screen.then_some((reply.root_x as f64, reply.root_y as f64)))
+    }
+
+    fn app_windows(&self) -> Result<Vec<AppWindow>, X11Error> {
+        let stacking = self.property32(
+            self.root,
+            self.atoms._NET_CLIENT_LIST_STACKING,
+            AtomEnum::WINDOW,
+        )?;
+
+        // the stacking list goes from bottom to top, cg lists front to back
+        Ok(stacking.into_iter().rev()
+            .filter_map(|w| match self.app_window(w) {
+                Ok(v) => v,
+                // the window can be destroyed while we are looking at it
+                Err(e) => {
+                    tracing::debug!("skipping window {w:#x}: {e}");
+                    None
+                },
+            })
+        .collect())
+    }
+}
+
+fn x11_window(window: &Window) -> xproto::Window {
+    match window.raw_window_handle() {
+        RawWindowHandle::Xlib(handle) => handle.window as _,
+        RawWindowHandle::Xcb(handle) => handle.window,
+        v => panic!("invalid window handle type: {v:?}"),
+    }
+}
+
+/// click through is already handled by `Window::set_cursor_hittest`,
+/// which clears the input shape of the window
+pub fn configure_window(window: &Window, monitor: &Monitor) {
+    generic::configure_window(window, monitor);
+
+    let res = match x11() {
+        Ok(x11) => x11.configure_overlay(x11_window(window)),
+        Err(e) => return tracing::warn!("could not configure overlay window: {e}"),
+    };
+    if let Err(e) = res {
+        tracing::warn!("could not configure overlay window: {e}");
+    }
+}
+
+pub fn window_source() -> Box<dyn WindowSource> {
+    match X11Windows::connect() {
+        Ok(v) => Box::new(v),
+        Err(e) => {
+            tracing::warn!("could not connect to x server, not listing windows: {e}");
+            Box::new(ScriptedWindows::default())
+        },
+    }
+}
+
+/// lists the top level windows managed by the window manager
+/// via `_NET_CLIENT_LIST_STACKING`
+pub struct X11Windows(&'static X11);
+
+impl X11Windows {
+    pub fn connect() -> Result<Self, &'static X11Error> {
+        x11().map(Self)
+    }
+}
+
+impl WindowSource for X11Windows {
+    fn windows(&mut self) -> Vec<AppWindow> {
+        self.0.app_windows().unwrap_or_else(|e| {
+            tracing::warn!("could not list windows: {e}");
+            Vec::new()
+        })
+    }
+}
+
+pub fn cursor_source() -> Box<dyn CursorSource> {
+    match x11() {
+        Ok(v) => Box::new(X11Cursor(v)),
+        Err(e) => {
+            tracing::warn!("could not connect to x server, not tracking the cursor: {e}");
+            Box::new(NoCursor)
+        },
+    }
+}
+
+/// machines without a battery have nothing in there
+pub fn power_source() -> Box<dyn PowerSource> {
+    Box::new(SysfsPower::new("/sys/class/power_supply"))
+}
+
+/// polls the pointer position on the root window
+pub struct X11Cursor(&'static X11);
+
+impl CursorSource for X11Cursor {
+    fn position(&mut self) -> Option<(f64, f64)> {
+        self.0.pointer().unwrap_or_else(|e| {
+            tracing::warn!("could not query pointer: {e}");
+            None
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use x11rb::protocol::xproto::{CreateWindowAux, WindowClass};
+
+    use super::*;
+
+    /// plays the window manager, so it wants a display without one:
+    /// `xvfb-run cargo test -- --ignored platform::x11`
+    #[test]
+    #[ignore]
+    fn lists_and_configures_windows() {
+        let x11 = x11().unwrap();
+        let (conn, a) = (&x11.conn, &x11.atoms);
+        let window = conn.generate_id().unwrap();
+        conn.create_window(
+            x11rb::COPY_DEPTH_FROM_PARENT, window, x11.root,
+            10, 20, 200, 100, 0,
+            WindowClass::INPUT_OUTPUT, x11rb::COPY_FROM_PARENT,
+            &CreateWindowAux::new(),
+        ).unwrap();
+
+        // not mapped yet, so it ends up in the properties
+        x11.configure_overlay(window).unwrap();
+        let state = x11.property32(window, a._NET_WM_STATE, AtomEnum::ATOM).unwrap();
+        for atom in [
+            a._NET_WM_STATE_ABOVE, a._NET_WM_STATE_STICKY,
+            a._NET_WM_STATE_SKIP_TASKBAR, a._NET_WM_STATE_SKIP_PAGER,
+        ] {
+            assert!(state.contains(&atom));
+        }
+        let desktop = x11.property32(window, a._NET_WM_DESKTOP, AtomEnum::CARDINAL).unwrap();
+        assert_eq!(desktop, [u32::MAX]);
+
+        conn.map_window(window).unwrap();
+        conn.change_property32(
+            PropMode::REPLACE, x11.root, a._NET_CLIENT_LIST_STACKING, AtomEnum::WINDOW, &[window],
+        ).unwrap();
+        conn.sync().unwrap();
+        let windows = X11Windows::connect().unwrap().windows();
+
+        conn.delete_property(x11.root, a._NET_CLIENT_LIST_STACKING).unwrap();
+        conn.destroy_window(window).unwrap();
+        conn.sync().unwrap();
+
+        let [app_window] = &windows[..] else { panic!("{windows:?}") };
+        assert_eq!(app_window.number, window as i64);
+        assert_eq!((app_window.pos, app_window.dim), ((10.0, 20.0), (200.0, 100.0)));
+        // above normal windows
+        assert_eq!(app_window.layer, FLOATING_LAYER);
+    }
+}
@@ -0,0 +1,266 @@
+//! follows the power state of laptops: the frame rate is capped while on
+//! battery and everything pauses when the battery runs low
+
+use std::time::{Duration, Instant};
+#[cfg(target_os = "linux")]
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::config::ConfigError;
+
+
+/// the `[power]` table of the config
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PowerConfig {
+    /// ignore the battery altogether
+    pub enabled: bool,
+    pub battery_fps: f32,
+    /// battery percentage to pause below
+    pub pause_below: f32,
+}
+
+impl Default for PowerConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            battery_fps: 30.0,
+            pause_below: 10.0,
+        }
+    }
+}
+
+impl PowerConfig {
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if !(self.battery_fps > 0.0 && self.battery_fps <= 1000.0) {
+            return Err(ConfigError::Invalid { key: "battery_fps", reason: "has to be in (0, 1000]" });
+        }
+        if !(0.0..=100.0).contains(&self.pause_below) {
+            return Err(ConfigError::Invalid { key: "pause_below", reason: "has to be in [0, 100]" });
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PowerState {
+    pub on_battery: bool,
+    /// charge of the batteries in [0, 100]
+    pub percent: f32,
+}
+
+/// something that knows whether we are running on battery
+pub trait PowerSource {
+    /// `None` without a battery
+    fn state(&mut self) -> Option<PowerState>;
+}
+
+/// for machines that are always plugged in, or where it is unknown
+#[derive(Debug, Default)]
+pub struct NoBattery;
+
+impl PowerSource for NoBattery {
+    fn state(&mut self) -> Option<PowerState> { None }
+}
+
+/// reads `/sys/class/power_supply`, or a directory laid out like it
+#[cfg(target_os = "linux")]
+pub struct SysfsPower {
+    dir: PathBuf,
+}
+
+#[cfg(target_os = "linux")]
+impl SysfsPower {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl PowerSource for SysfsPower {
+    fn state(&mut self) -> Option<PowerState> {
+        let read = |dir: &Path, name| std::fs::read_to_string(dir.join(name))
+            .map(|v| v.trim().to_string())
+            .ok();
+
+        let mut plugged_in = false;
+        let mut charges = Vec::new();
+        for entry in std::fs::read_dir(&self.dir).ok()?.flatten() {
+            let dir = entry.path();
+            match read(&dir, "type").as_deref() {
+                Some("Mains") => plugged_in |= read(&dir, "online").as_deref() == Some("1"),
+                Some("Battery") => {
+                    if let Some(v) = read(&dir, "capacity").and_then(|v| v.parse::<f32>().ok()) {
+                        charges.push(v);
+                    }
+                },
+                _ => (),
+            }
+        }
+
+        if charges.is_empty() {
+            return None;
+        }
+        Some(PowerState {
+            on_battery: !plugged_in,
+            percent: charges.iter().sum::<f32>() / charges.len() as f32,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PowerMode {
+    /// as fast as the monitors go
+    Full,
+    /// frames per second
+    Capped(f32),
+    Paused,
+}
+
+impl PowerMode {
+    pub fn new(config: &PowerConfig, state: Option<PowerState>) -> Self {
+        match state {
+            Some(state) if config.enabled && state.on_battery => {
+                if state.percent < config.pause_below {
+                    Self::Paused
+                } else {
+                    Self::Capped(config.battery_fps)
+                }
+            },
+            _ => Self::Full,
+        }
+    }
+
+    /// the time between frames, `None` when not capped
+    pub fn frame_interval(self) -> Option<Duration> {
+        match self {
+            Self::Capped(fps) => Some(Duration::from_secs_f32(1.0 / fps)),
+            Self::Full | Self::Paused => None,
+        }
+    }
+}
+
+/// polls a `PowerSource` and decides on a `PowerMode`
+pub struct PowerPolicy {
+    source: Box<dyn PowerSource>,
+    config: PowerConfig,
+    interval: Duration,
+    last_check: Option<Instant>,
+    state: Option<PowerState>,
+    mode: PowerMode,
+}
+
+impl PowerPolicy {
+    pub fn new(source: Box<dyn PowerSource>, config: PowerConfig, interval: Duration) -> Self {
+        Self {
+            source, config, interval,
+            last_check: None,
+            state: None,
+            mode: PowerMode::Full,
+        }
+    }
+
+    pub fn mode(&self) -> PowerMode { self.mode }
+    pub fn state(&self) -> Option<PowerState> { self.state }
+
+    pub fn set_config(&mut self, config: PowerConfig) {
+        self.config = config;
+        self.mode = PowerMode::new(&self.config, self.state);
+    }
+
+    /// the new mode if it changed, checks at most once per `interval`
+    pub fn poll(&mut self) -> Option<PowerMode> {
+        if self.last_check.is_some_and(|v| v.elapsed() < self.interval) {
+            return None;
+        }
+        self.last_check = Some(Instant::now());
+
+        self.state = self.source.state();
+        let mode = PowerMode::new(&self.config, self.state);
+        if mode == self.mode {
+            return None;
+        }
+        tracing::info!("power: {mode:?}, {:?}", self.state);
+        self.mode = mode;
+        Some(mode)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, rc::Rc};
+
+    use super::*;
+
+    /// reports whatever the test sets
+    struct MockPower(Rc<Cell<Option<PowerState>>>);
+
+    impl PowerSource for MockPower {
+        fn state(&mut self) -> Option<PowerState> { self.0.get() }
+    }
+
+    fn battery(percent: f32) -> Option<PowerState> {
+        Some(PowerState { on_battery: true, percent })
+    }
+
+    #[test]
+    fn policy_follows_battery() {
+        let state = Rc::new(Cell::new(Some(PowerState { on_battery: false, percent: 80.0 })));
+        let mut policy = PowerPolicy::new(
+            Box::new(MockPower(state.clone())),
+            PowerConfig::default(),
+            Duration::ZERO,
+        );
+        assert_eq!(policy.poll(), None);
+        assert_eq!(policy.mode(), PowerMode::Full);
+
+        state.set(battery(80.0));
+        assert_eq!(policy.poll(), Some(PowerMode::Capped(30.0)));
+        assert_eq!(policy.mode().frame_interval(), Some(Duration::from_secs_f32(1.0 / 30.0)));
+        state.set(battery(70.0));
+        assert_eq!(policy.poll(), None);
+
+        state.set(battery(5.0));
+        assert_eq!(policy.poll(), Some(PowerMode::Paused));
+        // plugged in, still low but charging
+        state.set(Some(PowerState { on_battery: false, percent: 5.0 }));
+        assert_eq!(policy.poll(), Some(PowerMode::Full));
+
+        state.set(battery(5.0));
+        policy.set_config(PowerConfig { enabled: false, ..Default::default() });
+        assert_eq!(policy.poll(), None);
+        assert_eq!(policy.mode(), PowerMode::Full);
+
+        // a desktop
+        state.set(None);
+        assert_eq!(policy.poll(), None);
+        policy.set_config(PowerConfig::default());
+        assert_eq!(policy.mode(), PowerMode::Full);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn reads_sysfs() {
+        let dir = std::env::temp_dir().join(format!("snow-power-{}", std::process::id()));
+        let supply = |name: &str, files: &[(&str, &str)]| {
+            std::fs::create_dir_all(dir.join(name)).unwrap();
+            for (file, v) in files {
+                std::fs::write(dir.join(name).join(file), format!("{v}\n")).unwrap();
+            }
+        };
+        let mut power = SysfsPower::new(&dir);
+
+        supply("AC", &[("type", "Mains"), ("online", "1")]);
+        assert_eq!(power.state(), None);
+
+        supply("BAT0", &[("type", "Battery"), ("capacity", "40")]);
+        supply("BAT1", &[("type", "Battery"), ("capacity", "60")]);
+        assert_eq!(power.state(), Some(PowerState { on_battery: false, percent: 50.0 }));
+
+        supply("AC", &[("online", "0")]);
+        assert_eq!(power.state(), battery(50.0));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,151 @@
+//! presets bundle the keys that make the particles snow, rain or
+//! something else: how they spawn, the forces on them, how long they
+//! rest and how they are drawn. keys set in the config override the
+//! ones of the preset
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::SnowConfig;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Preset {
+    #[default]
+    Snow,
+    Rain,
+    Leaves,
+    Confetti,
+    CherryBlossoms,
+}
+
+impl Preset {
+    pub const ALL: [Self; 5] = [Self::Snow, Self::Rain, Self::Leaves, Self::Confetti, Self::CherryBlossoms];
+
+    /// as written in the config and on the control socket
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Snow => "snow",
+            Self::Rain => "rain",
+            Self::Leaves => "leaves",
+            Self::Confetti => "confetti",
+            Self::CherryBlossoms => "cherry_blossoms",
+        }
+    }
+
+    /// for the menu
+    pub fn title(self) -> &'static str {
+        match self {
+            Self::Snow => "Snow",
+            Self::Rain => "Rain",
+            Self::Leaves => "Leaves",
+            Self::Confetti => "Confetti",
+            Self::CherryBlossoms => "Cherry blossoms",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|v| v.name() == name)
+    }
+
+    /// the config before any keys are set
+    pub fn config(self) -> SnowConfig {
+        let snow = SnowConfig::default();
+        match self {
+            Self::Snow => snow,
+            // too fast to swirl or tumble, and gone as soon as it lands
+            Self::Rain => SnowConfig {
+                preset: self,
+                style: Style::Streak,
+                particle_count: 2000,
+                gravity: [-150.0, -1500.0],
+                turbulence: 0.0,
+                max_speed: 4.0,
+                spin: 0.0,
+                max_age: 0.05,
+                flake_size: [0.003, 0.006],
+                flake_color: [0.7, 0.8, 1.0, 0.6],
+                ..snow
+            },
+            Self::Leaves => SnowConfig {
+                preset: self,
+                style: Style::Leaf,
+                particle_count: 150,
+                flutter: 20.0,
+                spin: 3.0,
+                max_age: 60.0,
+                flake_size: [0.012, 0.03],
+                palette: vec![[0.8, 0.3, 0.1], [0.9, 0.6, 0.1], [0.6, 0.2, 0.1], [0.7, 0.5, 0.2]],
+                cap_color: [0.55, 0.3, 0.12, 0.9],
+                ..snow
+            },
+            Self::Confetti => SnowConfig {
+                preset: self,
+                style: Style::Confetti,
+                particle_count: 400,
+                flutter: 8.0,
+                spin: 6.0,
+                max_age: 30.0,
+                flake_size: [0.006, 0.012],
+                palette: vec![[1.0, 0.2, 0.3], [0.2, 0.6, 1.0], [1.0, 0.85, 0.2], [0.3, 0.9, 0.4]],
+                cap_color: [0.9, 0.8, 0.85, 0.9],
+                ..snow
+            },
+            Self::CherryBlossoms => SnowConfig {
+                preset: self,
+                style: Style::Petal,
+                particle_count: 300,
+                flutter: 12.0,
+                spin: 2.0,
+                max_age: 40.0,
+                flake_size: [0.008, 0.016],
+                palette: vec![[1.0, 0.8, 0.86], [1.0, 0.9, 0.93], [0.98, 0.7, 0.8]],
+                cap_color: [1.0, 0.85, 0.9, 0.9],
+                ..snow
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for Preset {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// how `render.wgsl` draws a particle, `STYLE_*` there has to match
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Style {
+    /// dots, and crystals once they are large enough
+    #[default]
+    Crystal,
+    /// stretched along the velocity
+    Streak,
+    /// the rest are quads that tumble as they spin
+    Leaf,
+    Confetti,
+    Petal,
+}
+
+impl Style {
+    pub fn index(self) -> u32 {
+        self as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presets_are_valid() {
+        for preset in Preset::ALL {
+            let config = preset.config();
+            assert_eq!(config.preset, preset);
+            config.validate().unwrap_or_else(|e| panic!("{preset}: {e}"));
+            assert_eq!(Preset::from_name(preset.name()), Some(preset));
+        }
+        assert_eq!(Preset::from_name("hail"), None);
+    }
+}
@@ -0,0 +1,125 @@
+//! the wgsl shaders, baked in or reloaded from disk while working on them
+
+use std::{
+    path::PathBuf,
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::utils::modified;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shader {
+    Render,
+    Rect,
+    Cap,
+    Simulate,
+}
+
+impl Shader {
+    pub const ALL: [Self; 4] = [Self::Render, Self::Rect, Self::Cap, Self::Simulate];
+
+    pub fn file_name(self) -> &'static str {
+        match self {
+            Self::Render => "render.wgsl",
+            Self::Rect => "rect.wgsl",
+            Self::Cap => "cap.wgsl",
+            Self::Simulate => "simulate.wgsl",
+        }
+    }
+
+    /// the baked in source
+    pub fn source(self) -> &'static str {
+        match self {
+            Self::Render => include_str!("shaders/render.wgsl"),
+            Self::Rect => include_str!("shaders/rect.wgsl"),
+            Self::Cap => include_str!("shaders/cap.wgsl"),
+            Self::Simulate => include_str!("shaders/simulate.wgsl"),
+        }
+    }
+
+    pub fn create_module(self, device: &wgpu::Device, src: &str) -> wgpu::ShaderModule {
+        device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some(self.file_name()),
+            source: wgpu::ShaderSource::Wgsl(src.into()),
+        })
+    }
+}
+
+/// polls the shaders in a directory for changes
+pub struct ShaderWatcher {
+    dir: PathBuf,
+    interval: Duration,
+    last_check: Option<Instant>,
+    modified: [Option<SystemTime>; Shader::ALL.len()],
+}
+
+impl ShaderWatcher {
+    /// the first `poll` returns every shader found in `dir`
+    pub fn new(dir: PathBuf, interval: Duration) -> Self {
+        Self { dir, interval, last_check: None, modified: Default::default() }
+    }
+
+    /// the next `poll` returns every shader again
+    pub fn reset(&mut self) {
+        self.last_check = None;
+        self.modified = Default::default();
+    }
+
+    /// the shaders that changed since the last call and their source,
+    /// checks at most once per `interval`
+    pub fn poll(&mut self) -> Vec<(Shader, String)> {
+        if self.last_check.is_some_and(|v| v.elapsed() < self.interval) {
+            return Vec::new();
+        }
+        self.last_check = Some(Instant::now());
+
+        Shader::ALL.into_iter().zip(&mut self.modified)
+            .filter_map(|(shader, last)| {
+                let path = self.dir.join(shader.file_name());
+                let modified = modified(&path);
+                // a deleted shader keeps the current pipelines
+                if modified.is_none() || modified == *last {
+                    return None;
+                }
+                *last = modified;
+                match std::fs::read_to_string(&path) {
+                    Ok(src) => Some((shader, src)),
+                    Err(e) => {
+                        tracing::warn!("could not read {path:?}: {e}");
+                        None
+                    },
+                }
+            })
+        .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::SnowConfig, headless::{self, HeadlessState}, snow::SimBackend};
+
+    #[test]
+    fn keeps_pipelines_on_errors() {
+        let Some((device, queue, sim)) = headless::test_device() else { return };
+        let config = SnowConfig::default();
+        let mut reference = HeadlessState::new(&device, 64, 64, &config, 0x5eed, sim);
+        let mut state = HeadlessState::new(&device, 64, 64, &config, 0x5eed, sim);
+
+        for shader in Shader::ALL {
+            if shader == Shader::Simulate && sim == SimBackend::Cpu { continue }
+            let broken = shader.source().replace("fn ", "fnn ");
+            assert!(state.snow_mut().reload_shader(&device, shader, &broken).is_err());
+        }
+        // still draws with the baked in shaders
+        let expected = reference.render(&device, &queue, 1.0 / 60.0);
+        assert_eq!(state.render(&device, &queue, 1.0 / 60.0).data, expected.data);
+
+        for shader in Shader::ALL {
+            state.snow_mut().reload_shader(&device, shader, shader.source()).unwrap();
+        }
+        let expected = reference.render(&device, &queue, 1.0 / 60.0);
+        assert_eq!(state.render(&device, &queue, 1.0 / 60.0).data, expected.data);
+    }
+}
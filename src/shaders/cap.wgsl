@@ -0,0 +1,109 @@
+struct VertexOutput {
+    @builtin(position) clip_pos: vec4<f32>,
+    // distance below the surface of the cap
+    @location(0) depth: f32,
+}
+
+struct InstanceInput {
+    @location(10) pos: vec2<f32>,
+    @location(11) dim: vec2<f32>,
+    @location(12) cap: u32,
+}
+
+struct ShaderData {
+    dt: f32,
+    time: f32,
+    gravity: vec2<f32>,
+    aspect: f32,
+    max_age: f32,
+    window_count: u32,
+    melt_rate: f32,
+    cursor: vec2<f32>,
+    cursor_vel: vec2<f32>,
+    flake_size: vec2<f32>,
+    active_count: u32,
+    parallax: f32,
+    wind: vec2<f32>,
+    turbulence: f32,
+    gust: f32,
+    depth_of_field: f32,
+    depth_fade: f32,
+    style: u32,
+    max_speed: f32,
+    flake_color: vec4<f32>,
+    cap_color: vec4<f32>,
+    drag: f32,
+    flutter: f32,
+    max_spin: f32,
+    palette_count: u32,
+    palette: array<vec4<f32>, 4>,
+}
+
+@group(0) @binding(0)
+var<uniform> data: ShaderData;
+
+// maps simulation space to the target, see `snow::ViewData`
+struct View {
+    offset: vec2<f32>,
+    scale: vec2<f32>,
+}
+
+@group(0) @binding(1)
+var<uniform> view: View;
+
+// the snow caps, `CAP_COLUMNS` per row. row 0 is the bottom of the screen
+@group(1) @binding(0)
+var heights: texture_2d<u32>;
+
+// keep in sync with `snow::CAP_COLUMNS`
+const CAP_COLUMNS: i32 = 64;
+const CAP_UNIT: f32 = 0.000001;
+
+fn height(cap: u32, column: i32) -> f32 {
+    // falls off at the window edges
+    if column < 0 || column >= CAP_COLUMNS { return 0.0; }
+    return f32(textureLoad(heights, vec2<i32>(column, i32(cap)), 0).x) * CAP_UNIT;
+}
+
+@vertex
+fn vertex_main(
+    @builtin(vertex_index) vertex: u32,
+    instance: InstanceInput,
+) -> VertexOutput {
+    var out: VertexOutput;
+
+    // two triangles per column, x along the column and y up to the surface
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(0.0, 1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(1.0, 0.0),
+        vec2<f32>(1.0, 0.0),
+        vec2<f32>(0.0, 0.0),
+        vec2<f32>(0.0, 1.0),
+    );
+    let corner = corners[vertex % 6u];
+    let column = i32(vertex / 6u);
+
+    // the surface is smoothed between neighbouring columns
+    let center = height(instance.cap, column);
+    let left = (height(instance.cap, column - 1) + center) * 0.5;
+    let right = (center + height(instance.cap, column + 1)) * 0.5;
+    let surface = mix(left, right, corner.x);
+
+    let x = instance.pos.x + (f32(column) + corner.x) / f32(CAP_COLUMNS) * instance.dim.x;
+    let top = -(instance.pos.y * 2.0 - 1.0);
+    let pos = vec2<f32>(x * 2.0 - 1.0, top + surface * corner.y);
+    out.clip_pos = vec4<f32>((pos - view.offset) * view.scale, 0.0, 1.0);
+    out.depth = surface * (1.0 - corner.y);
+
+    return out;
+}
+
+@fragment
+fn fragment_main(
+    vertex: VertexOutput,
+) -> @location(0) vec4<f32> {
+    // soft towards the surface
+    let blend = smoothstep(0.0, 0.004, vertex.depth);
+    return vec4<f32>(data.cap_color.rgb, data.cap_color.a * blend);
+}
@@ -12,6 +12,15 @@ struct InstanceInput {
     @location(11) dim: vec2<f32>,
 }
 
+// maps simulation space to the target, see `snow::ViewData`
+struct View {
+    offset: vec2<f32>,
+    scale: vec2<f32>,
+}
+
+@group(0) @binding(1)
+var<uniform> view: View;
+
 @vertex
 fn vertex_main(
     model: VertexInput,
@@ -20,8 +29,8 @@ fn vertex_main(
     var out: VertexOutput;
 
     let pos = ((model.pos + 1.0) / 2.0) * instance.dim + instance.pos;
-    let p = vec2<f32>(pos.x, -pos.y) * 4.0 + vec2<f32>(-1.0, 1.0);
-    out.clip_pos = vec4<f32>(vec3<f32>(p, 0.0), 1.0);
+    let p = vec2<f32>(pos.x, -pos.y) * 2.0 + vec2<f32>(-1.0, 1.0);
+    out.clip_pos = vec4<f32>(vec3<f32>((p - view.offset) * view.scale, 0.0), 1.0);
 
     return out;
 }
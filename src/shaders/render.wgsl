@@ -2,7 +2,20 @@
 
 struct VertexOutput {
     @builtin(position) clip_pos: vec4<f32>,
+    // rotated with the flake
     @location(0) pos: vec2<f32>,
+    @location(1) @interpolate(flat) shape: u32,
+    // 0 for a dot, 1 for a crystal
+    @location(2) crystal: f32,
+    @location(3) @interpolate(flat) sprite: u32,
+    // how far the edges are smeared out, in the units of `pos`
+    @location(4) blur: f32,
+    // multiplies the alpha
+    @location(5) opacity: f32,
+    // multiplies the color, darker while a tumbling particle is edge on
+    @location(6) shade: f32,
+    // how much longer than wide a streak is
+    @location(7) stretch: f32,
 }
 
 struct VertexInput {
@@ -15,18 +28,66 @@ struct ShaderData {
     gravity: vec2<f32>,
     aspect: f32,
     max_age: f32,
+    window_count: u32,
+    melt_rate: f32,
+    cursor: vec2<f32>,
+    cursor_vel: vec2<f32>,
+    flake_size: vec2<f32>,
+    active_count: u32,
+    parallax: f32,
+    wind: vec2<f32>,
+    turbulence: f32,
+    gust: f32,
+    depth_of_field: f32,
+    depth_fade: f32,
+    style: u32,
+    max_speed: f32,
+    flake_color: vec4<f32>,
+    cap_color: vec4<f32>,
+    drag: f32,
+    flutter: f32,
+    max_spin: f32,
+    palette_count: u32,
+    palette: array<vec4<f32>, 4>,
 }
 
 struct InstanceInput {
-    @location(10) pos: vec2<f32>,
-    @location(11) vel: vec2<f32>,
-    @location(12) scale: f32,
-    @location(13) age: f32,
+    @location(1) pos: vec2<f32>,
+    @location(2) vel: vec2<f32>,
+    @location(3) scale: f32,
+    @location(4) age: f32,
+    @location(6) angle: f32,
+    @location(8) shape: u32,
+    @location(9) sprite: u32,
+    @location(10) depth: f32,
 }
 
 @group(0) @binding(0)
 var<uniform> data: ShaderData;
 
+// maps simulation space to the target, see `snow::ViewData`
+struct View {
+    offset: vec2<f32>,
+    scale: vec2<f32>,
+}
+
+@group(0) @binding(1)
+var<uniform> view: View;
+
+// see `snow::SpriteData`
+struct Sprites {
+    grid: vec2<u32>,
+    // 0 without a sprite sheet
+    count: u32,
+}
+
+@group(1) @binding(0)
+var sprite_sheet: texture_2d<f32>;
+@group(1) @binding(1)
+var sprite_sampler: sampler;
+@group(1) @binding(2)
+var<uniform> sprites: Sprites;
+
 @vertex
 fn vertex_main(
     model: VertexInput,
@@ -34,24 +95,233 @@ fn vertex_main(
 ) -> VertexOutput {
     var out: VertexOutput;
 
-    out.clip_pos = vec4<f32>(
-        vec3<f32>(
-            model.pos / vec2<f32>(data.aspect, 1.0) * instance.scale + instance.pos,
-            0.0,
-        ), 1.0,
-    );
+    let size = instance.scale * depth_scale(instance.depth);
+    // the corner in screen heights around the particle
+    var corner = model.pos;
+    out.pos = rotate(model.pos, instance.angle);
+    out.shade = 1.0;
+    out.stretch = 1.0;
+    if data.style == STYLE_STREAK {
+        // along the velocity, longer the faster
+        let vel = instance.vel * vec2<f32>(data.aspect, 1.0);
+        let speed = length(vel);
+        let dir = select(vec2<f32>(0.0, -1.0), vel / speed, speed > 0.0001);
+        out.stretch = 1.0 + speed * STREAK_LENGTH;
+        corner = vec2<f32>(dir.y, -dir.x) * model.pos.x + dir * model.pos.y * out.stretch;
+        out.pos = vec2<f32>(model.pos.x, model.pos.y * out.stretch);
+    } else if data.style != STYLE_CRYSTAL {
+        // flips around its long axis as it spins, each at its own phase
+        let phase = f32(instance.shape & 0xffffu) / 65536.0 * 2.0 * PI;
+        let tumble = abs(cos(instance.angle * TUMBLE + phase));
+        corner = rotate(model.pos * vec2<f32>(max(tumble, MIN_TUMBLE), 1.0), instance.angle);
+        out.pos = model.pos;
+        out.shade = mix(EDGE_SHADE, 1.0, tumble);
+    }
+    let pos = corner / vec2<f32>(data.aspect, 1.0) * size + instance.pos;
+    out.clip_pos = vec4<f32>(vec3<f32>((pos - view.offset) * view.scale, 0.0), 1.0);
 
-    out.pos = model.pos;
+    out.shape = instance.shape;
+    out.crystal = smoothstep(CRYSTAL_SIZE.x, CRYSTAL_SIZE.y, size);
+    out.sprite = instance.sprite;
+    // near flakes are out of focus, far ones fade into the background
+    let near = 1.0 - instance.depth;
+    out.blur = MAX_BLUR * data.depth_of_field * near * near;
+    out.opacity = 1.0 - data.depth_fade * instance.depth;
 
     return out;
 }
 
+// keep in sync with `preset::Style`
+const STYLE_CRYSTAL: u32 = 0u;
+const STYLE_STREAK: u32 = 1u;
+const STYLE_LEAF: u32 = 2u;
+const STYLE_CONFETTI: u32 = 3u;
+const STYLE_PETAL: u32 = 4u;
+
+// how much a streak grows per unit of speed
+const STREAK_LENGTH: f32 = 3.0;
+// tumbling particles turn this many times faster than they spin
+const TUMBLE: f32 = 1.7;
+// the width a tumbling particle keeps while edge on, so it doesn't vanish
+const MIN_TUMBLE: f32 = 0.15;
+// and how dark it gets
+const EDGE_SHADE: f32 = 0.6;
+
+fn rotate(p: vec2<f32>, angle: f32) -> vec2<f32> {
+    let sin_v = sin(angle);
+    let cos_v = cos(angle);
+    return vec2<f32>(p.x * cos_v - p.y * sin_v, p.x * sin_v + p.y * cos_v);
+}
+
+// keep in sync with `snow::depth_scale`
+const NEAR_SCALE: f32 = 0.8;
+const FAR_SCALE: f32 = 0.5;
+// the blur of the nearest flakes at full depth of field
+const MAX_BLUR: f32 = 0.3;
+
+// the size of a flake at `depth` relative to its scale
+fn depth_scale(depth: f32) -> f32 {
+    return mix(1.0 + NEAR_SCALE * data.parallax, 1.0 - FAR_SCALE * data.parallax, depth);
+}
+
+// flakes smaller than this, in screen heights, stay dots. in between they fade over
+const CRYSTAL_SIZE: vec2<f32> = vec2<f32>(0.006, 0.01);
+const PI: f32 = 3.141592653589793;
+// the arms reach this far out of the quad
+const ARM_LENGTH: f32 = 0.6;
+
+// half the size of a sprite, so it covers about as much as a crystal
+const SPRITE_RADIUS: f32 = 0.6;
+
+// the color of the flake's sprite at `pos`
+fn sprite_color(pos: vec2<f32>, sprite: u32) -> vec4<f32> {
+    let cell = sprite % sprites.count;
+    let grid = vec2<f32>(sprites.grid);
+    // y is up in the flake, down in the sheet. half a texel in from
+    // the edges, so the neighbours don't bleed in
+    let half_texel = grid * 0.5 / vec2<f32>(textureDimensions(sprite_sheet));
+    let uv = vec2<f32>(pos.x, -pos.y) / (2.0 * SPRITE_RADIUS) + 0.5;
+    if any(uv < vec2<f32>(0.0)) || any(uv > vec2<f32>(1.0)) {
+        return vec4<f32>(0.0);
+    }
+    let origin = vec2<f32>(f32(cell % sprites.grid.x), f32(cell / sprites.grid.x));
+    let texel = (origin + clamp(uv, half_texel, 1.0 - half_texel)) / grid;
+    return textureSampleLevel(sprite_sheet, sprite_sampler, texel, 0.0);
+}
+
+// the sheet has no mipmaps, so out of focus sprites average a few taps
+fn blurred_sprite(pos: vec2<f32>, sprite: u32, blur: f32) -> vec4<f32> {
+    if blur <= 0.0 {
+        return sprite_color(pos, sprite);
+    }
+    var sum = sprite_color(pos, sprite);
+    for (var i = 0; i < 6; i++) {
+        let angle = f32(i) * PI / 3.0;
+        sum += sprite_color(pos + vec2<f32>(cos(angle), sin(angle)) * blur, sprite);
+    }
+    return sum / 7.0;
+}
+
+// four values in [0, 1) from the shape seed
+fn shape_params(shape: u32) -> vec4<f32> {
+    var h = shape * 747796405u + 2891336453u;
+    var out = vec4<f32>(0.0);
+    for (var i = 0; i < 4; i++) {
+        h = h * 747796405u + 2891336453u;
+        let word = ((h >> ((h >> 28u) + 4u)) ^ h) * 277803737u;
+        out[i] = f32(((word >> 22u) ^ word) >> 8u) / 16777216.0;
+    }
+    return out;
+}
+
+fn segment(p: vec2<f32>, a: vec2<f32>, b: vec2<f32>) -> f32 {
+    let t = clamp(dot(p - a, b - a) / dot(b - a, b - a), 0.0, 1.0);
+    return length(p - a - (b - a) * t);
+}
+
+// signed distance to a six-fold crystal: a hexagonal plate in the
+// middle and arms with side branches, `params` decides the proportions
+fn crystal(pos: vec2<f32>, params: vec4<f32>) -> f32 {
+    // fold into the twelfth of the plane between an arm and a mirror line
+    let sector = PI / 3.0;
+    let a = abs(((atan2(pos.y, pos.x) % sector) + sector) % sector - sector * 0.5);
+    let angle = sector * 0.5 - a;
+    let p = length(pos) * vec2<f32>(cos(angle), sin(angle));
+
+    // mostly plates or mostly dendrites
+    let plate_size = mix(0.1, 0.45, params.x * params.x);
+    let plate = p.x * cos(PI / 6.0) + p.y * sin(PI / 6.0) - plate_size * cos(PI / 6.0);
+
+    let width = mix(0.02, 0.06, params.w);
+    let arm_length = ARM_LENGTH * mix(0.7, 1.0, params.y);
+    var d = min(plate, segment(p, vec2<f32>(0.0), vec2<f32>(arm_length, 0.0)) - width);
+    // side branches, pointing outwards at 60°
+    let branch_dir = vec2<f32>(cos(sector), sin(sector));
+    for (var i = 1; i <= 3; i++) {
+        let t = f32(i) / 4.0;
+        let start = vec2<f32>(arm_length * mix(0.25, 0.85, t), 0.0);
+        let branch = arm_length * mix(0.1, 0.45, params.z) * (1.0 - t * 0.7);
+        d = min(d, segment(p, start, start + branch_dir * branch) - width * 0.7);
+    }
+    return d;
+}
+
+// the color of the particle from the palette, white without one
+fn palette_color(shape: u32) -> vec3<f32> {
+    if data.palette_count == 0u {
+        return vec3<f32>(1.0);
+    }
+    return data.palette[shape % data.palette_count].rgb;
+}
+
+// a drop with its head at +y and a fading tail
+fn streak(pos: vec2<f32>, stretch: f32, edge: f32) -> f32 {
+    let half_length = max(stretch - 0.5, 0.0);
+    let d = segment(pos, vec2<f32>(0.0, -half_length), vec2<f32>(0.0, half_length)) - 0.2;
+    return smoothstep(edge, -edge, d) * smoothstep(-stretch, stretch, pos.y);
+}
+
+// a pointed leaf with a stalk, along y
+fn leaf(pos: vec2<f32>) -> f32 {
+    // the intersection of two circles of radius `r`, `d` off center
+    let r = 0.9;
+    let d = 0.55;
+    let p = abs(pos);
+    let b = sqrt(r * r - d * d);
+    var blade = length(p + vec2<f32>(d, 0.0)) - r;
+    if (p.y - b) * d > p.x * b {
+        blade = length(p - vec2<f32>(0.0, b));
+    }
+    let stalk = segment(pos, vec2<f32>(0.0, -b), vec2<f32>(0.0, -0.95)) - 0.04;
+    return min(blade, stalk);
+}
+
+fn confetti(pos: vec2<f32>) -> f32 {
+    let q = abs(pos) - vec2<f32>(0.45, 0.8);
+    return length(max(q, vec2<f32>(0.0))) + min(max(q.x, q.y), 0.0);
+}
+
+// an oval with a notch at the tip
+fn petal(pos: vec2<f32>) -> f32 {
+    let oval = (length(pos / vec2<f32>(0.55, 0.85)) - 1.0) * 0.55;
+    return max(oval, 0.25 - length(pos - vec2<f32>(0.0, 0.9)));
+}
 
 @fragment
 fn fragment_main(
     vertex: VertexOutput,
 ) -> @location(0) vec4<f32> {
-    let blend = smoothstep(0.6, 0.5, length(vertex.pos * vec2<f32>(1.0, 1.0)));
-    return vec4<f32>(vec3<f32>(1.0), blend);
+    let fade = vec4<f32>(1.0, 1.0, 1.0, vertex.opacity);
+    if sprites.count > 0u {
+        return blurred_sprite(vertex.pos, vertex.sprite, vertex.blur) * data.flake_color * fade;
+    }
+    // about a pixel, outside of the branches so the derivatives are defined
+    let edge = max(length(fwidth(vertex.pos)), 0.001) + vertex.blur;
+    var color = data.flake_color.rgb * palette_color(vertex.shape) * vertex.shade;
+    var blend = 0.0;
+    switch data.style {
+        case STYLE_STREAK: {
+            blend = streak(vertex.pos, vertex.stretch, edge);
+        }
+        case STYLE_LEAF: {
+            blend = smoothstep(edge, -edge, leaf(vertex.pos));
+            // darker along the vein
+            color *= mix(0.7, 1.0, smoothstep(0.0, 0.08, abs(vertex.pos.x)));
+        }
+        case STYLE_CONFETTI: {
+            blend = smoothstep(edge, -edge, confetti(vertex.pos));
+        }
+        case STYLE_PETAL: {
+            blend = smoothstep(edge, -edge, petal(vertex.pos));
+        }
+        default: {
+            blend = smoothstep(0.6 + vertex.blur, 0.5 - vertex.blur, length(vertex.pos));
+            if vertex.crystal > 0.0 {
+                let d = crystal(vertex.pos, shape_params(vertex.shape));
+                blend = mix(blend, smoothstep(edge, -edge, d), vertex.crystal);
+            }
+        }
+    }
+    return vec4<f32>(color, data.flake_color.a * blend * vertex.opacity);
 }
 
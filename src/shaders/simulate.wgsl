@@ -5,6 +5,32 @@ struct Instance {
     vel: vec2<f32>,
     scale: f32,
     age: f32,
+    // pcg state and stream
+    rng: vec2<u32>,
+    angle: f32,
+    spin: f32,
+    shape: u32,
+    sprite: u32,
+    depth: f32,
+}
+
+// a window in [0, 1] screen space, origin at the top left
+struct Rect {
+    pos: vec2<f32>,
+    dim: vec2<f32>,
+    // row of its snow cap in the heightfield
+    cap: u32,
+}
+
+// the snow of a cap whose window moved or closed
+struct Shed {
+    // where the window was
+    pos: vec2<f32>,
+    dim: vec2<f32>,
+    // of the window, in simulation space per second
+    vel: vec2<f32>,
+    // 0 for an unused slot
+    cap: u32,
 }
 
 struct ShaderData {
@@ -13,6 +39,27 @@ struct ShaderData {
     gravity: vec2<f32>,
     aspect: f32,
     max_age: f32,
+    window_count: u32,
+    melt_rate: f32,
+    cursor: vec2<f32>,
+    cursor_vel: vec2<f32>,
+    flake_size: vec2<f32>,
+    active_count: u32,
+    parallax: f32,
+    wind: vec2<f32>,
+    turbulence: f32,
+    gust: f32,
+    depth_of_field: f32,
+    depth_fade: f32,
+    style: u32,
+    max_speed: f32,
+    flake_color: vec4<f32>,
+    cap_color: vec4<f32>,
+    drag: f32,
+    flutter: f32,
+    max_spin: f32,
+    palette_count: u32,
+    palette: array<vec4<f32>, 4>,
 }
 
 @group(0) @binding(0)
@@ -21,22 +68,184 @@ var<uniform> data: ShaderData;
 @group(1) @binding(0)
 var<storage, read_write> instances: array<Instance>;
 
+@group(1) @binding(1)
+var<storage, read> windows: array<Rect>;
+
+// the snow caps, `CAP_COLUMNS` per row. row 0 is the bottom of the screen
+@group(1) @binding(2)
+var<storage, read_write> heights: array<u32>;
+
+// the flakes that settled this frame
+@group(1) @binding(3)
+var<storage, read_write> pending: array<atomic<u32>>;
+
+// the caps that fall off as flakes this frame
+@group(1) @binding(4)
+var<storage, read> shed: array<Shed>;
+
+
+// pcg-rxs-m-xs-32, returns a float in [0, 1)
+fn rand(rng: ptr<function, vec2<u32>>) -> f32 {
+    let state = (*rng).x;
+    (*rng).x = state * 747796405u + (*rng).y;
+    var word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    word = (word >> 22u) ^ word;
+    return f32(word >> 8u) / 16777216.0;
+}
+
+// the curl of `amp / freq * sin(freq * dot(dir, p) + speed * t + phase)`
+fn wave(p: vec2<f32>, t: f32, dir: vec2<f32>, freq: f32, speed: f32, phase: f32, amp: f32) -> vec2<f32> {
+    return amp * cos(freq * dot(dir, p) + speed * t + phase) * vec2<f32>(dir.y, -dir.x);
+}
+
+// turbulence without sources or sinks, so flakes swirl instead of bunching up.
+// the curl of a few travelling waves, about unit length
+fn curl_noise(p: vec2<f32>, t: f32) -> vec2<f32> {
+    return wave(p, t, vec2<f32>(0.8, 0.6), 1.3, 0.31, 0.0, 0.4)
+         + wave(p, t, vec2<f32>(-0.6, 0.8), 2.9, -0.47, 1.7, 0.3)
+         + wave(p, t, vec2<f32>(0.28, -0.96), 5.3, 0.73, 3.4, 0.2)
+         + wave(p, t, vec2<f32>(-0.96, -0.28), 11.0, -1.1, 5.1, 0.1);
+}
+
+// the size and speed of a flake at `depth` relative to its scale
+fn depth_scale(depth: f32) -> f32 {
+    return mix(1.0 + NEAR_SCALE * data.parallax, 1.0 - FAR_SCALE * data.parallax, depth);
+}
+
+// the wind a flake at `pos` feels, gusts also stir up the turbulence
+fn wind_at(pos: vec2<f32>) -> vec2<f32> {
+    let swirl = curl_noise(pos * vec2<f32>(data.aspect, 1.0), data.time);
+    return data.wind + swirl * data.turbulence * (1.0 + data.gust);
+}
+
+// keep in sync with `snow::depth_scale`
+const NEAR_SCALE: f32 = 0.8;
+const FAR_SCALE: f32 = 0.5;
+const TAU: f32 = 6.283185307179586;
+
+// how far a flake may already be below a window top and still land on it
+const LAND_EPSILON: f32 = 0.0001;
+
+// keep in sync with `snow::CAP_COLUMNS`
+const CAP_COLUMNS: u32 = 64u;
+// the heightfield is fixed point, so settling flakes can be added atomically
+const CAP_UNIT: f32 = 0.000001;
+// the highest a cap gets
+const CAP_MAX: f32 = 0.05;
+// how much a settled flake adds to a cap
+const CAP_GAIN: f32 = 1.0;
+// seconds a flake rests before it becomes part of the cap below it
+const SETTLE_TIME: f32 = 1.0;
+
+// how far around its path the cursor pushes flakes, relative to the screen height
+const CURSOR_RADIUS: f32 = 0.15;
+
+// the velocity a flake at `pos` gets from the cursor sweeping past it this frame,
+// partly along the cursor and partly away from it
+fn cursor_push(pos: vec2<f32>) -> vec2<f32> {
+    let speed = length(data.cursor_vel);
+    if speed < 0.001 { return vec2<f32>(0.0); }
+
+    let aspect = vec2<f32>(data.aspect, 1.0);
+    let end = data.cursor * aspect;
+    let start = (data.cursor - data.cursor_vel * data.dt) * aspect;
+    let path = end - start;
+    let p = pos * aspect;
+    let t = clamp(dot(p - start, path) / max(dot(path, path), 0.00000001), 0.0, 1.0);
+    let offset = p - (start + path * t);
+    let dist = length(offset);
+    if dist > CURSOR_RADIUS { return vec2<f32>(0.0); }
+
+    let away = offset / max(dist, 0.0001) / aspect;
+    let falloff = 1.0 - dist / CURSOR_RADIUS;
+    return (data.cursor_vel * 0.6 + away * speed * 0.4) * falloff;
+}
+
+// particles faster than `max_speed` slow down again, so pushed ones do not fly off forever
+fn drag(vel: vec2<f32>) -> vec2<f32> {
+    let speed = length(vel);
+    if speed <= data.max_speed { return vel; }
+    return vel * max(data.max_speed / speed, exp(-data.drag * data.dt));
+}
+
+// (left, right, top, bottom) in simulation space
+fn rect_bounds(rect: Rect) -> vec4<f32> {
+    let lo = rect.pos * 2.0 - 1.0;
+    let hi = (rect.pos + rect.dim) * 2.0 - 1.0;
+    return vec4<f32>(lo.x, hi.x, -lo.y, -hi.y);
+}
+
+fn rect_contains(bounds: vec4<f32>, p: vec2<f32>) -> bool {
+    return p.x >= bounds.x && p.x <= bounds.y
+        && p.y <= bounds.z && p.y >= bounds.w;
+}
 
-fn rand(co: vec2<f32>) -> f32 {
-    return fract(sin(dot(co, vec2(12.9898, 78.233))) * 43758.5453);
+// windows are sorted front to back, so only the ones
+// before `w` can hide parts of it
+fn covered(w: u32, p: vec2<f32>) -> bool {
+    for (var j = 0u; j < w; j++) {
+        if rect_contains(rect_bounds(windows[j]), p) { return true; }
+    }
+    return false;
+}
+
+struct Landing {
+    rest: f32,
+    // index into the heightfield, -1 if the flake did not land
+    cell: i32,
+    // of a heightfield column
+    width: f32,
 }
 
-fn simple_noise(v: f32) -> f32 {
-    return sin(v * 0.2) * 0.4
-         + sin(v * 0.9) * 0.2
-         + sin(v * 5.0) * 0.05
-         + sin(v * 0.5) * 0.35;
+// lands on the cap on top of `bounds`, the windows before `w` are in front of it.
+// a resting flake is caught again every frame, so it falls
+// as soon as the window below it moves or closes
+fn land_on(bounds: vec4<f32>, cap: u32, w: u32, prev: vec2<f32>, pos: vec2<f32>, offset: f32) -> Landing {
+    var out = Landing(0.0, -1, 0.0);
+    if pos.x < bounds.x || pos.x > bounds.y { return out; }
+    let t = (pos.x - bounds.x) / (bounds.y - bounds.x);
+    let column = min(u32(max(t, 0.0) * f32(CAP_COLUMNS)), CAP_COLUMNS - 1u);
+    let cell = cap * CAP_COLUMNS + column;
+    let rest = bounds.z + f32(heights[cell]) * CAP_UNIT + offset;
+    if prev.y < bounds.z + offset - LAND_EPSILON || pos.y > rest { return out; }
+    if covered(w, vec2<f32>(pos.x, bounds.z)) { return out; }
+    out.rest = rest;
+    out.cell = i32(cell);
+    out.width = (bounds.y - bounds.x) / f32(CAP_COLUMNS);
+    return out;
 }
 
-fn rotate(v: vec2<f32>, a: f32) -> vec2<f32> {
-    let sin_v = sin(a);
-    let cos_v = cos(a);
-    return vec2<f32>(v.x * cos_v - v.y * sin_v, v.x * sin_v + v.y * cos_v);
+// where a flake moving from `prev` to `pos` lands
+fn land(prev: vec2<f32>, pos: vec2<f32>, offset: f32) -> Landing {
+    for (var w = 0u; w < data.window_count; w++) {
+        let landing = land_on(rect_bounds(windows[w]), windows[w].cap, w, prev, pos, offset);
+        if landing.cell >= 0 { return landing; }
+    }
+    // the bottom of the screen, behind all windows
+    let bottom = vec4<f32>(-1.0, 1.0, -1.0, -1.0);
+    return land_on(bottom, 0u, data.window_count, prev, pos, offset);
+}
+
+// turns flake `i` into the snow of a column of a shed cap, if there is
+// any. every shed cap takes `CAP_COLUMNS` flakes, one per column
+fn shed_flake(i: u32) -> bool {
+    let s = i / CAP_COLUMNS;
+    if s >= arrayLength(&shed) { return false; }
+    let cap = shed[s];
+    let column = i % CAP_COLUMNS;
+    let height = f32(heights[cap.cap * CAP_COLUMNS + column]) * CAP_UNIT;
+    if cap.cap == 0u || height <= 0.0 { return false; }
+
+    // as big as the flakes that settled there
+    let width = cap.dim.x * 2.0 / f32(CAP_COLUMNS);
+    let size = sqrt(height * width / CAP_GAIN);
+    let x = (cap.pos.x + (f32(column) + 0.5) / f32(CAP_COLUMNS) * cap.dim.x) * 2.0 - 1.0;
+    let top = -(cap.pos.y * 2.0 - 1.0);
+    instances[i].pos = vec2<f32>(x, top + height * 0.5);
+    instances[i].vel = cap.vel;
+    instances[i].scale = size / depth_scale(instances[i].depth);
+    instances[i].age = 0.0;
+    return true;
 }
 
 @compute
@@ -47,21 +256,63 @@ fn main(
     let padding = 0.1;
     // let padding = -0.1;
     let i = global_id.x;
+    if i >= data.active_count { return; }
+    if shed_flake(i) { return; }
 
     var pos = instances[i].pos;
     var vel = instances[i].vel;
+    var rng = instances[i].rng;
     if length(vel) < 0.01 {
         instances[i].age += data.dt;
     }
 
-    let rot = simple_noise(pos.y + pos.x / 10.0 + data.time / 1.0) * 1.0;
+    let prev = pos;
+    let wind = wind_at(pos);
+    // swaying from side to side as it spins
+    let flutter = vec2<f32>(sin(instances[i].angle) * data.flutter, 0.0);
+    vel = drag(vel + cursor_push(pos));
     pos += vel * data.dt * 0.9;
-    vel += rotate(data.gravity, rot) * data.dt * instances[i].scale;
+    // near flakes look bigger and move faster
+    let size = instances[i].scale * depth_scale(instances[i].depth);
+    vel += (data.gravity + wind + flutter) * data.dt * size;
 
-    if pos.y + padding < -1.0 || instances[i].age > data.max_age {
-        instances[i].scale = (rand(pos) * 1.4 + 0.1) * 0.01;
-        pos.x = rand(vel) * 2.0 - 1.0;
-        pos.y += 2.0 * (1.0 + padding);
+    // sit on the edge instead of the center
+    var settled = false;
+    let landing = land(prev, pos, size * 0.5);
+    if landing.cell >= 0 {
+        pos.y = landing.rest;
+        vel = vec2<f32>(0.0);
+        // rested long enough, becomes part of the cap
+        if instances[i].age > SETTLE_TIME {
+            let height = size * size * CAP_GAIN / landing.width;
+            atomicAdd(&pending[landing.cell], u32(height / CAP_UNIT));
+            settled = true;
+        }
+    }
+
+    // resting flakes stop spinning
+    if landing.cell < 0 {
+        instances[i].angle = (instances[i].angle + instances[i].spin * data.dt) % TAU;
+    }
+
+    let fell = pos.y + padding < -1.0;
+    if fell || settled || instances[i].age > data.max_age {
+        // every later respawn and the goldens depend on the order of the
+        // draws, so don't add or reorder any
+        instances[i].scale = data.flake_size.x + (data.flake_size.y - data.flake_size.x) * rand(&rng);
+        pos.x = rand(&rng) * 2.0 - 1.0;
+        instances[i].angle = rand(&rng) * TAU;
+        instances[i].spin = (rand(&rng) * 2.0 - 1.0) * data.max_spin;
+        // the 24 bits of a `rand`
+        instances[i].shape = u32(rand(&rng) * 16777216.0);
+        instances[i].sprite = u32(rand(&rng) * 16777216.0);
+        // more far flakes than near ones
+        instances[i].depth = sqrt(rand(&rng));
+        if fell {
+            pos.y += 2.0 * (1.0 + padding);
+        } else {
+            pos.y = 1.0 + padding;
+        }
         vel = vec2<f32>(0.0);
         instances[i].age = 0.0;
     }
@@ -72,5 +323,25 @@ fn main(
 
     instances[i].pos = pos;
     instances[i].vel = vel;
+    instances[i].rng = rng;
 }
 
+// merges the flakes that settled into the caps and melts them a bit
+@compute
+@workgroup_size(256)
+fn melt(
+    @builtin(global_invocation_id) global_id: vec3<u32>,
+) {
+    let i = global_id.x;
+    if i >= arrayLength(&heights) { return; }
+
+    let melted = u32(data.melt_rate * data.dt / CAP_UNIT);
+    let merged = min(heights[i] + atomicExchange(&pending[i], 0u), u32(CAP_MAX / CAP_UNIT));
+    heights[i] = merged - min(merged, melted);
+
+    // their snow is falling as flakes now
+    let row = i / CAP_COLUMNS;
+    for (var s = 0u; s < arrayLength(&shed); s++) {
+        if row != 0u && shed[s].cap == row { heights[i] = 0u; }
+    }
+}
@@ -1,22 +1,29 @@
-use std::{sync::{Arc, RwLock}, time::Instant, ffi::c_void, collections::HashMap};
+use std::{time::{Duration, Instant}, collections::HashMap, path::PathBuf};
 
 use rand::prelude::*;
 use bytemuck::{Zeroable, Pod};
-use icrate::{AppKit::{NSView, NSScreen, self}, Foundation::{NSDictionary, ns_string, NSString, NSNumber}};
-use rand::rngs::ThreadRng;
-use raw_window_handle::{
-    HasRawWindowHandle, RawWindowHandle,
-};
-use objc2::{rc::{Id, autoreleasepool}, Message, runtime::NSObject};
+use rand::rngs::StdRng;
 use winit::{
-    monitor::MonitorHandle,
     window::{Window, WindowBuilder, WindowLevel, WindowId},
-    event_loop::EventLoop, error::{OsError, ExternalError}, event::WindowEvent
+    event_loop::EventLoopWindowTarget, error::{OsError, ExternalError}, event::WindowEvent,
+    dpi::PhysicalSize,
 };
-use wgpu::{util::{DeviceExt, BufferInitDescriptor}, include_wgsl};
+use wgpu::util::{DeviceExt, BufferInitDescriptor};
 use wrld::{Desc, DescInstance};
 
-use crate::utils::UniformBuffer;
+use crate::{
+    budget::{Budget, GpuTimer},
+    config::{ConfigError, SnowConfig, MAX_PALETTE},
+    cpu,
+    cursor::{CursorMotion, CursorSource},
+    pacing::{self, FrameClock},
+    platform::{self, Monitor},
+    shader::Shader,
+    sprites::{SpriteError, SpriteSheet},
+    utils::UniformBuffer,
+    wind::Wind,
+    windows::{AppWindow, CapSlots, Region, WindowSource},
+};
 
 // vertex buffer
 #[repr(C)]
@@ -25,34 +32,200 @@ struct SnowflakeVertex {
     #[f32x2(0)] pos: [f32; 2],
 }
 
-// instance buffer
+// instance buffer, starts at location 1 to stay within 16 attributes
+#[repr(C)]
+#[derive(Pod, Zeroable, DescInstance, Clone, Copy, Debug, PartialEq)]
+pub struct SnowflakeInstance {
+    #[f32x2(1)] pub pos: [f32; 2],
+    #[f32x2(2)] pub vel: [f32; 2],
+    #[f32(3)] pub scale: f32,
+    #[f32(4)] pub age: f32,
+    // pcg state and stream, used for respawning
+    #[u32x2(5)] pub rng: [u32; 2],
+    // in radians, spinning by `spin` per second
+    #[f32(6)] pub angle: f32,
+    #[f32(7)] pub spin: f32,
+    // picks the crystal it is drawn as
+    #[u32(8)] pub shape: u32,
+    // picks the sprite when there is a sprite sheet, wraps around
+    #[u32(9)] pub sprite: u32,
+    // 0 is nearest, 1 farthest, see `depth_scale`
+    #[f32(10)] pub depth: f32,
+    #[u32(11)] pub _padding: u32,
+}
+
+/// how much bigger the nearest and smaller the farthest flakes are at full
+/// parallax. the constants in `simulate.wgsl` and `render.wgsl` have to match
+pub const NEAR_SCALE: f32 = 0.8;
+pub const FAR_SCALE: f32 = 0.5;
+
+/// the size and speed of a flake at `depth` relative to its `scale`
+pub fn depth_scale(depth: f32, parallax: f32) -> f32 {
+    let near = 1.0 + NEAR_SCALE * parallax;
+    let far = 1.0 - FAR_SCALE * parallax;
+    near + (far - near) * depth
+}
+
+// a window in [0, 1] screen space, origin at the top left
 #[repr(C)]
-#[derive(Pod, Zeroable, DescInstance, Clone, Copy)]
-struct SnowflakeInstance {
-    #[f32x2(10)] pos: [f32; 2],
-    #[f32x2(11)] vel: [f32; 2],
-    #[f32(12)] scale: f32,
-    #[f32(13)] age: f32,
+#[derive(Pod, Zeroable, DescInstance, Clone, Copy, Debug)]
+pub struct RectInstance {
+    #[f32x2(10)] pub pos: [f32; 2],
+    #[f32x2(11)] pub dim: [f32; 2],
+    // row of its snow cap in the heightfield
+    #[u32(12)] pub cap: u32,
+    #[u32(13)] pub _padding: u32,
 }
 
+// the snow of a cap whose window moved or closed, it falls off as
+// flakes from where the window was
 #[repr(C)]
-#[derive(Pod, Zeroable, DescInstance, Clone, Copy)]
-struct RectInstance {
-    #[f32x2(10)] pos: [f32; 2],
-    #[f32x2(11)] dim: [f32; 2],
+#[derive(Pod, Zeroable, Clone, Copy, Debug, PartialEq)]
+pub struct ShedCap {
+    pub pos: [f32; 2],
+    pub dim: [f32; 2],
+    // of the window, in simulation space per second
+    pub vel: [f32; 2],
+    // the row in the heightfield, 0 for an unused slot
+    pub cap: u32,
+    pub _padding: u32,
 }
 
 // uniform
 #[derive(Pod, Zeroable, Clone, Copy)]
 #[repr(C)]
-struct FrameData {
-    dt: f32,
-    time: f32,
-    gravity: [f32; 2],
-    aspect: f32,
-    max_age: f32,
+pub struct FrameData {
+    pub dt: f32,
+    pub time: f32,
+    pub gravity: [f32; 2],
+    pub aspect: f32,
+    pub max_age: f32,
+    pub window_count: u32,
+    pub melt_rate: f32,
+    // in simulation space, the velocity is per second
+    pub cursor: [f32; 2],
+    pub cursor_vel: [f32; 2],
+    // the range of flake sizes
+    pub flake_size: [f32; 2],
+    // the prefix of the instances that is stepped and drawn
+    pub active_count: u32,
+    // how much depth changes the size and speed of flakes, in [0, 1]
+    pub parallax: f32,
+    // the base wind and the current gust, set every frame
+    pub wind: [f32; 2],
+    // scales the curl noise the flakes swirl in
+    pub turbulence: f32,
+    // how strong the current gust is, in [0, 1]
+    pub gust: f32,
+    // how blurry the nearest flakes are and how faint the farthest, in [0, 1]
+    pub depth_of_field: f32,
+    pub depth_fade: f32,
+    // how the particles are drawn, see `Style`
+    pub style: u32,
+    // particles faster than this slow down at `drag` per second
+    pub max_speed: f32,
+    pub flake_color: [f32; 4],
+    pub cap_color: [f32; 4],
+    pub drag: f32,
+    pub flutter: f32,
+    // the fastest particles spin when they respawn, in radians per second
+    pub max_spin: f32,
+    // the first `palette_count` colors of `palette` are used
+    pub palette_count: u32,
+    pub palette: [[f32; 4]; MAX_PALETTE],
+}
+
+/// the palette of `config` padded for `FrameData`
+fn palette(config: &SnowConfig) -> (u32, [[f32; 4]; MAX_PALETTE]) {
+    let mut palette = [[0.0; 4]; MAX_PALETTE];
+    for (out, [r, g, b]) in palette.iter_mut().zip(&config.palette) {
+        *out = [*r, *g, *b, 1.0];
+    }
+    (config.palette.len().min(MAX_PALETTE) as u32, palette)
+}
+
+// uniform, maps simulation space to the clip space of a target
+#[derive(Pod, Zeroable, Clone, Copy, Debug, PartialEq)]
+#[repr(C)]
+pub struct ViewData {
+    pub offset: [f32; 2],
+    pub scale: [f32; 2],
+}
+
+impl ViewData {
+    /// the whole simulation space
+    pub const IDENTITY: Self = Self { offset: [0.0, 0.0], scale: [1.0, 1.0] };
+
+    /// shows `region` of the simulation space that covers `sim`
+    pub fn new(region: Region, sim: Region) -> Self {
+        let (x, y) = region.origin;
+        let [left, top] = sim.to_sim((x, y));
+        let [right, bottom] = sim.to_sim((x + region.dim.0, y + region.dim.1));
+        Self {
+            offset: [(left + right) * 0.5, (top + bottom) * 0.5],
+            // y is up in simulation space
+            scale: [2.0 / (right - left), 2.0 / (top - bottom)],
+        }
+    }
+}
+
+// uniform, where the sprites are in the sheet
+#[derive(Pod, Zeroable, Clone, Copy, Debug, PartialEq)]
+#[repr(C)]
+pub struct SpriteData {
+    pub grid: [u32; 2],
+    // 0 without a sheet, the flakes are crystals then
+    pub count: u32,
+    pub _padding: u32,
+}
+
+/// the sprite sheet on the gpu, a blank texture without one
+struct Sprites {
+    _texture: wgpu::Texture,
+    data: UniformBuffer<SpriteData>,
+    bind_group: wgpu::BindGroup,
+    /// the file and grid it was loaded from
+    source: Option<(PathBuf, [u32; 2])>,
+}
+
+/// what a target shows of a `Snow`, created by `Snow::create_view`
+pub struct View {
+    data: UniformBuffer<ViewData>,
+    bind_group: wgpu::BindGroup,
+}
+
+impl View {
+    pub fn set(&mut self, queue: &wgpu::Queue, data: ViewData) {
+        *self.data = data;
+        self.data.write(queue);
+    }
 }
 
+/// columns in the heightfield of every snow cap,
+/// `CAP_COLUMNS` in the shaders has to match
+pub const CAP_COLUMNS: usize = 64;
+
+
+/// where the particles are stepped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SimBackend {
+    /// the compute shader in `simulate.wgsl`
+    Gpu,
+    /// `cpu::step`, the instances are uploaded every frame
+    Cpu,
+}
+
+impl SimBackend {
+    pub fn for_adapter(adapter: &wgpu::Adapter) -> Self {
+        let caps = adapter.get_downlevel_capabilities();
+        if caps.flags.contains(wgpu::DownlevelFlags::COMPUTE_SHADERS) {
+            Self::Gpu
+        } else {
+            tracing::warn!("adapter has no compute shaders, simulating on the cpu");
+            Self::Cpu
+        }
+    }
+}
 
 #[derive(Debug, thiserror::Error)]
 pub enum BuildError {
@@ -64,87 +237,139 @@ pub enum BuildError {
 
     #[error(transparent)]
     SurfaceCreation(#[from] wgpu::CreateSurfaceError),
+
+    #[error("could not find a suitable adapter")]
+    NoAdapter,
+
+    #[error(transparent)]
+    RequestDevice(#[from] wgpu::RequestDeviceError),
+
+    #[error(transparent)]
+    Config(#[from] ConfigError),
+
+    #[error(transparent)]
+    Sprites(#[from] SpriteError),
 }
 
-pub struct SnowState {
-    running: bool,
-    creation: Instant,
-    last_draw: Instant,
-    rng: ThreadRng,
+/// the gpu side of the simulation. owns the particles and everything
+/// needed to step them and draw them into a color target
+pub struct Snow {
+    rng: StdRng,
 
     particle_count: usize,
     instance_buffer: wgpu::Buffer,
     vertex_count: usize,
     vertex_buffer: wgpu::Buffer,
     frame_data: UniformBuffer<FrameData>,
+    background: wgpu::Color,
     window_buffer: wgpu::Buffer,
-    windows: HashMap<i64, AppWindow>,
+    window_count: usize,
     max_windows: usize,
-
-    fg_surface: wgpu::Surface,
-    fg_config: wgpu::SurfaceConfiguration,
-
-    /// the bindgroup containing all uniforms
-    uniform_bind_group: wgpu::BindGroup,
+    /// the windows for the cpu simulation
+    window_rects: Vec<RectInstance>,
+    /// draw the window rects below the particles, for debugging
+    draw_windows: bool,
+    /// the cap along the bottom of the screen, drawn like a window
+    bottom_buffer: wgpu::Buffer,
+    /// the cap heightfields for drawing, one row per cap
+    cap_texture: wgpu::Texture,
+    cap_bind_group: wgpu::BindGroup,
+    sprites: Sprites,
+    sprite_bind_group_layout: wgpu::BindGroupLayout,
+    /// the cap heightfield, the flakes that settled this frame and the
+    /// caps to shed, `None` when simulating on the cpu
+    cap_buffers: Option<(wgpu::Buffer, wgpu::Buffer, wgpu::Buffer)>,
+    /// the shed caps have to be cleared again
+    shedding: bool,
+
+    /// the uniforms with a view of everything, for stepping and `encode`
+    view: View,
+    uniform_bind_group_layout: wgpu::BindGroupLayout,
     /// the bindgroup containing the storage buffer
-    /// of the instances
-    compute_bind_group: wgpu::BindGroup,
-
+    /// of the instances, `None` when simulating on the cpu
+    compute_bind_group: Option<wgpu::BindGroup>,
+    compute_bind_group_layout: Option<wgpu::BindGroupLayout>,
+    /// the instances when simulating on the cpu
+    cpu_instances: Option<Vec<SnowflakeInstance>>,
+    /// the caps when simulating on the cpu
+    cpu_caps: Option<cpu::Caps>,
+
+    /// kept to rebuild the pipelines when a shader is reloaded
+    format: wgpu::TextureFormat,
+    layouts: PipelineLayouts,
     render_pipeline: wgpu::RenderPipeline,
     rect_pipeline: wgpu::RenderPipeline,
-    sim_pipeline: wgpu::ComputePipeline,
+    cap_pipeline: wgpu::RenderPipeline,
+    sim_pipeline: Option<wgpu::ComputePipeline>,
+    melt_pipeline: Option<wgpu::ComputePipeline>,
+}
 
-    pub fg_window: Window,
-    size: winit::dpi::PhysicalSize<u32>,
-    monitor: Id<NSScreen>,
+struct PipelineLayouts {
+    render: wgpu::PipelineLayout,
+    rect: wgpu::PipelineLayout,
+    cap: wgpu::PipelineLayout,
+    /// `None` when simulating on the cpu
+    sim: Option<wgpu::PipelineLayout>,
 }
 
-impl SnowState {
-    pub fn new<E>(
-        device: &wgpu::Device,
-        instance: &wgpu::Instance,
-        adapter: &wgpu::Adapter,
+/// a snow and everything that drives it: the clock, the wind, the
+/// windows it settles on and the cursor. it covers `region` of the desktop
+pub struct Simulation {
+    snow: Snow,
+    creation: Instant,
+    last_draw: Instant,
+    clock: FrameClock,
+    region: Region,
+    wind: Wind,
 
-        particle_count: usize,
-        monitor: Id<NSScreen>,
-        event_loop: &EventLoop<E>,
-    ) -> Result<Self, BuildError> {
-        dbg!(get_windows());
+    windows: HashMap<i64, AppWindow>,
+    window_source: Box<dyn WindowSource>,
+    cap_slots: CapSlots,
+    cursor_source: Box<dyn CursorSource>,
+    cursor_motion: CursorMotion,
+
+    /// `None` without a `target_fps`
+    budget: Option<Budget>,
+    /// seconds between paced frames, for the budget
+    frame_interval: f32,
+    /// the cpu time spent on the last frame in `update` and `encode` or `step`
+    work: Duration,
+    /// `None` without timestamp queries
+    timer: Option<GpuTimer>,
+}
 
-        let fg_window = WindowBuilder::new()
-            .with_title("snow-fg")
-            .with_transparent(true)
-            .with_decorations(false)
-            .with_window_level(WindowLevel::AlwaysOnTop)
-        .build(event_loop)?;
+/// what a monitor draws
+enum Sim {
+    /// its own snow, only on this monitor
+    Own(Box<Simulation>),
+    /// its part of the snow shared by all monitors
+    Shared(View),
+}
 
-        fg_window.set_cursor_hittest(false)?;
-        configure_window(&fg_window, monitor.clone());
-        
-        let size = fg_window.inner_size();
+pub struct SnowState {
+    running: bool,
+    sim: Sim,
 
-        let aspect = size.width as f32 / size.height as f32;
+    fg_surface: wgpu::Surface,
+    fg_config: wgpu::SurfaceConfiguration,
 
-        let fg_surface = unsafe { instance.create_surface(&fg_window) }?;
-        let fg_caps = fg_surface.get_capabilities(adapter);
-        let fg_format = fg_caps.formats.iter()
-            .copied()
-            .filter(|f| f.is_srgb())
-            .next()
-        .unwrap_or(fg_caps.formats[0]);
-
-        let fg_config = wgpu::SurfaceConfiguration {
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-            format: fg_format,
-            width: size.width,
-            height: size.height,
-            present_mode: fg_caps.present_modes[0],
-            alpha_mode: wgpu::CompositeAlphaMode::PostMultiplied,
-            view_formats: vec![],
-        };
-        fg_surface.configure(&device, &fg_config);
+    pub fg_window: Window,
+    size: winit::dpi::PhysicalSize<u32>,
+    monitor: Monitor,
+}
 
-        let mut rng = rand::thread_rng();
+impl Snow {
+    pub fn new(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        config: &SnowConfig,
+        aspect: f32,
+        seed: u64,
+        sim: SimBackend,
+    ) -> Self {
+        // the same seed always gives the same snowfall,
+        // respawns are driven by the per instance rng
+        let mut rng = StdRng::seed_from_u64(seed);
 
         let vertecies = &[
             SnowflakeVertex { pos: [-1.0, 1.0] },
@@ -161,42 +386,84 @@ impl SnowState {
         });
         let vertex_count = vertecies.len();
 
+        let particle_count = config.particle_count;
+        let instances = spawn_instances(&mut rng, 0..particle_count, config.flake_size, config.spin);
+
         let instance_buffer = device.create_buffer_init(&BufferInitDescriptor {
             label: Some("snow-instance"),
-            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::STORAGE,
-            contents: bytemuck::cast_slice(
-                &(0..particle_count).map(|_| {
-                    let pos = [
-                        rng.gen_range(-1.0..1.0),
-                        rng.gen_range(-1.0..1.0) * (1.0 + 0.05),
-                    ];
-
-                    SnowflakeInstance {
-                        pos,
-                        vel: [0.0, 0.0],
-                        scale: rng.gen_range(0.1..1.5) * 0.01,
-                        age: 0.0,
-                    }
-                }).collect::<Vec<_>>()
-            )
+            usage: match sim {
+                SimBackend::Gpu => wgpu::BufferUsages::STORAGE,
+                SimBackend::Cpu => wgpu::BufferUsages::empty(),
+            } | wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::COPY_DST,
+            contents: bytemuck::cast_slice(&instances),
         });
 
-        let max_windows = 100usize;
+        let max_windows = config.max_windows;
         let window_buffer = device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("window instance"),
-            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
             size: std::mem::size_of::<RectInstance>() as u64 * max_windows as u64,
             mapped_at_creation: false,
         });
-        let windows = HashMap::new();
 
+        // row 0 is the bottom of the screen, the windows use the rows after it
+        let cap_rows = max_windows + 1;
+        let bottom_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("bottom instance"),
+            usage: wgpu::BufferUsages::VERTEX,
+            contents: bytemuck::cast_slice(&[RectInstance {
+                pos: [0.0, 1.0],
+                dim: [1.0, 0.0],
+                cap: 0,
+                _padding: 0,
+            }]),
+        });
+        let cap_texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("cap heights"),
+            size: wgpu::Extent3d {
+                width: CAP_COLUMNS as u32,
+                height: cap_rows as u32,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::R32Uint,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+
+        let (palette_count, palette) = palette(config);
         let frame_data = UniformBuffer::new(device, FrameData {
             aspect,
             dt: 0.0,
             time: 0.0,
-            gravity: [0.1, -1.0],
-            max_age: 100.0,
+            gravity: config.gravity,
+            max_age: config.max_age,
+            window_count: 0,
+            melt_rate: config.melt_rate,
+            cursor: [0.0, 0.0],
+            cursor_vel: [0.0, 0.0],
+            flake_size: config.flake_size,
+            active_count: particle_count as u32,
+            parallax: config.parallax,
+            wind: config.wind,
+            turbulence: config.turbulence,
+            gust: 0.0,
+            depth_of_field: config.depth_of_field,
+            depth_fade: config.depth_fade,
+            style: config.style.index(),
+            max_speed: config.max_speed,
+            flake_color: config.flake_color,
+            cap_color: config.cap_color,
+            drag: config.drag,
+            flutter: config.flutter,
+            max_spin: config.spin,
+            palette_count,
+            palette,
         }, Some("frame data"));
+        let [r, g, b, a] = config.background.map(f64::from);
+        let background = wgpu::Color { r, g, b, a };
 
 
         let uniform_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
@@ -204,279 +471,632 @@ impl SnowState {
             entries: &[
                 wgpu::BindGroupLayoutEntry {
                     binding: 0,
-                    visibility: wgpu::ShaderStages::COMPUTE | wgpu::ShaderStages::VERTEX,
+                    visibility: match sim {
+                        SimBackend::Gpu => wgpu::ShaderStages::COMPUTE,
+                        SimBackend::Cpu => wgpu::ShaderStages::NONE,
+                    } | wgpu::ShaderStages::VERTEX_FRAGMENT,
                     ty: frame_data.binding_ty(),
                     count: None,
                 },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: wgpu::BufferSize::new(std::mem::size_of::<ViewData>() as _),
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let view = create_view(device, &uniform_bind_group_layout, &frame_data, ViewData::IDENTITY);
+
+        let render_shader = Shader::Render.create_module(device, Shader::Render.source());
+        let rect_shader = Shader::Rect.create_module(device, Shader::Rect.source());
+        let cap_shader = Shader::Cap.create_module(device, Shader::Cap.source());
+
+        let cap_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("cap bind group layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Uint,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
             ],
         });
 
-        let uniform_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("uniform bind group"),
-            layout: &uniform_bind_group_layout,
+        let cap_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("cap bind group"),
+            layout: &cap_bind_group_layout,
             entries: &[
                 wgpu::BindGroupEntry {
                     binding: 0,
-                    resource: frame_data.buffer().as_entire_binding(),
+                    resource: wgpu::BindingResource::TextureView(
+                        &cap_texture.create_view(&wgpu::TextureViewDescriptor::default()),
+                    ),
                 },
             ],
         });
 
-        let compute_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            label: Some("compute bind group layout"),
+        let sprite_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("sprite bind group layout"),
             entries: &[
                 wgpu::BindGroupLayoutEntry {
                     binding: 0,
-                    visibility: wgpu::ShaderStages::COMPUTE,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
                     ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Storage { read_only: false },
+                        ty: wgpu::BufferBindingType::Uniform,
                         has_dynamic_offset: false,
-                        min_binding_size: None,
+                        min_binding_size: wgpu::BufferSize::new(std::mem::size_of::<SpriteData>() as _),
                     },
                     count: None,
                 },
             ],
         });
-
-        let compute_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("compute bind group"),
-            layout: &compute_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: instance_buffer.as_entire_binding(),
-                },
-            ]
-        });
-
-        let render_shader = device.create_shader_module(
-            include_wgsl!("shaders/render.wgsl")
-        );
-        let sim_shader = device.create_shader_module(
-            include_wgsl!("shaders/simulate.wgsl")
-        );
-        let rect_shader = device.create_shader_module(
-            include_wgsl!("shaders/rect.wgsl")
-        );
+        let sprites = create_sprites(device, &sprite_bind_group_layout, None);
 
         let render_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: Some("render pipeline layout"),
-            bind_group_layouts: &[&uniform_bind_group_layout],
+            bind_group_layouts: &[&uniform_bind_group_layout, &sprite_bind_group_layout],
             push_constant_ranges: &[],
         });
 
-        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("render pipeline"),
-            layout: Some(&render_pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &render_shader,
-                buffers: &[SnowflakeVertex::desc(), SnowflakeInstance::desc()],
-                entry_point: "vertex_main",
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &render_shader,
-                entry_point: "fragment_main",
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: fg_config.format,
-                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: None,
-                polygon_mode: wgpu::PolygonMode::Fill,
-                unclipped_depth: false,
-                conservative: false,
-            },
-            depth_stencil: None,
-            multisample: wgpu::MultisampleState {
-                count: 1,
-                mask: !0,
-                alpha_to_coverage_enabled: false,
-            },
-            multiview: None,
-        });
+        let render_pipeline = create_render_pipeline(
+            device, "render pipeline", &render_pipeline_layout,
+            &render_shader, &[SnowflakeVertex::desc(), SnowflakeInstance::desc()], format,
+        );
 
         let rect_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: Some("rect pipeline layout"),
-            bind_group_layouts: &[],
+            bind_group_layouts: &[&uniform_bind_group_layout],
             push_constant_ranges: &[],
         });
 
-        let rect_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("rect pipeline"),
-            layout: Some(&rect_pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &rect_shader,
-                buffers: &[SnowflakeVertex::desc(), RectInstance::desc()],
-                entry_point: "vertex_main",
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &rect_shader,
-                entry_point: "fragment_main",
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: fg_config.format,
-                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: None,
-                polygon_mode: wgpu::PolygonMode::Fill,
-                unclipped_depth: false,
-                conservative: false,
-            },
-            depth_stencil: None,
-            multisample: wgpu::MultisampleState {
-                count: 1,
-                mask: !0,
-                alpha_to_coverage_enabled: false,
-            },
-            multiview: None,
-        });
+        let rect_pipeline = create_render_pipeline(
+            device, "rect pipeline", &rect_pipeline_layout,
+            &rect_shader, &[SnowflakeVertex::desc(), RectInstance::desc()], format,
+        );
 
-        let sim_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-            label: Some("compute pipeline layout"),
-            bind_group_layouts: &[&uniform_bind_group_layout, &compute_bind_group_layout],
+        let cap_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("cap pipeline layout"),
+            bind_group_layouts: &[&uniform_bind_group_layout, &cap_bind_group_layout],
             push_constant_ranges: &[],
         });
 
-        let sim_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-            label: Some("sim pipeline"),
-            layout: Some(&sim_pipeline_layout),
-            module: &sim_shader,
-            entry_point: "main",
-        });
+        let cap_pipeline = create_render_pipeline(
+            device, "cap pipeline", &cap_pipeline_layout,
+            &cap_shader, &[RectInstance::desc()], format,
+        );
 
-        let creation = Instant::now();
-        let last_draw = Instant::now();
-        // info: maybe set to false?
-        let running = true;
+        let (compute_bind_group_layout, compute_bind_group, sim, cap_buffers, cpu_instances, cpu_caps) = match sim {
+            SimBackend::Gpu => {
+                let compute_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    label: Some("compute bind group layout"),
+                    entries: &[
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 0,
+                            visibility: wgpu::ShaderStages::COMPUTE,
+                            ty: wgpu::BindingType::Buffer {
+                                ty: wgpu::BufferBindingType::Storage { read_only: false },
+                                has_dynamic_offset: false,
+                                min_binding_size: None,
+                            },
+                            count: None,
+                        },
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 1,
+                            visibility: wgpu::ShaderStages::COMPUTE,
+                            ty: wgpu::BindingType::Buffer {
+                                ty: wgpu::BufferBindingType::Storage { read_only: true },
+                                has_dynamic_offset: false,
+                                min_binding_size: None,
+                            },
+                            count: None,
+                        },
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 2,
+                            visibility: wgpu::ShaderStages::COMPUTE,
+                            ty: wgpu::BindingType::Buffer {
+                                ty: wgpu::BufferBindingType::Storage { read_only: false },
+                                has_dynamic_offset: false,
+                                min_binding_size: None,
+                            },
+                            count: None,
+                        },
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 3,
+                            visibility: wgpu::ShaderStages::COMPUTE,
+                            ty: wgpu::BindingType::Buffer {
+                                ty: wgpu::BufferBindingType::Storage { read_only: false },
+                                has_dynamic_offset: false,
+                                min_binding_size: None,
+                            },
+                            count: None,
+                        },
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 4,
+                            visibility: wgpu::ShaderStages::COMPUTE,
+                            ty: wgpu::BindingType::Buffer {
+                                ty: wgpu::BufferBindingType::Storage { read_only: true },
+                                has_dynamic_offset: false,
+                                min_binding_size: None,
+                            },
+                            count: None,
+                        },
+                    ],
+                });
+
+                let cap_size = (cap_rows * CAP_COLUMNS * std::mem::size_of::<u32>()) as u64;
+                let height_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("cap heights"),
+                    usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::COPY_DST,
+                    size: cap_size,
+                    mapped_at_creation: false,
+                });
+                let pending_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("cap pending"),
+                    usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+                    size: cap_size,
+                    mapped_at_creation: false,
+                });
+
+                // at most every window sheds its cap at once
+                let shed_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("cap shed"),
+                    usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+                    size: std::mem::size_of::<ShedCap>() as u64 * max_windows as u64,
+                    mapped_at_creation: false,
+                });
+
+                let cap_buffers = (height_buffer, pending_buffer, shed_buffer);
+                let compute_bind_group = create_compute_bind_group(
+                    device, &compute_bind_group_layout,
+                    &instance_buffer, &window_buffer, &cap_buffers,
+                );
+
+                let sim_shader = Shader::Simulate.create_module(device, Shader::Simulate.source());
+
+                let sim_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                    label: Some("compute pipeline layout"),
+                    bind_group_layouts: &[&uniform_bind_group_layout, &compute_bind_group_layout],
+                    push_constant_ranges: &[],
+                });
+
+                let (sim_pipeline, melt_pipeline) = create_sim_pipelines(
+                    device, &sim_pipeline_layout, &sim_shader,
+                );
+
+                (
+                    Some(compute_bind_group_layout), Some(compute_bind_group),
+                    Some((sim_pipeline_layout, sim_pipeline, melt_pipeline)),
+                    Some(cap_buffers), None, None,
+                )
+            },
+            SimBackend::Cpu => (
+                None, None, None, None,
+                Some(instances), Some(cpu::Caps::new(cap_rows)),
+            ),
+        };
+        let (sim_pipeline_layout, sim_pipeline, melt_pipeline) = match sim {
+            Some((layout, sim, melt)) => (Some(layout), Some(sim), Some(melt)),
+            None => (None, None, None),
+        };
 
-        Ok(Self {
+        Self {
             instance_buffer, vertex_buffer,
             vertex_count, particle_count,
-            window_buffer, windows,
-            fg_surface, fg_config,
-            fg_window, size, monitor,
-            creation, running, last_draw,
-            frame_data, rng,
-
-            uniform_bind_group,
+            window_buffer, max_windows,
+            window_count: 0,
+            window_rects: Vec::new(),
+            draw_windows: false,
+            bottom_buffer, cap_texture,
+            cap_bind_group, cap_buffers,
+            shedding: false,
+            sprites, sprite_bind_group_layout,
+            frame_data, background, rng,
+
+            view,
+            uniform_bind_group_layout,
+            compute_bind_group_layout,
             compute_bind_group,
+            cpu_instances,
+            cpu_caps,
+            format,
+            layouts: PipelineLayouts {
+                render: render_pipeline_layout,
+                rect: rect_pipeline_layout,
+                cap: cap_pipeline_layout,
+                sim: sim_pipeline_layout,
+            },
             render_pipeline,
             rect_pipeline,
+            cap_pipeline,
             sim_pipeline,
-            max_windows,
-        })
+            melt_pipeline,
+        }
     }
 
-    pub fn window_id(&self) -> WindowId { self.fg_window.id() }
+    pub fn particle_count(&self) -> usize { self.particle_count }
+    pub fn active_count(&self) -> usize { self.frame_data.active_count as usize }
+    pub fn format(&self) -> wgpu::TextureFormat { self.format }
 
-    pub fn set_running(&mut self, v: bool) {
-        tracing::info!("set running: {v}");
-        if v { self.redraw() }
-        self.running = v;
-    }
+    /// applies a changed config in place, keeping the flakes and caps.
+    /// another `style` respawns the flakes, only `max_windows` needs a restart
+    pub fn apply_config(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        config: &SnowConfig,
+    ) {
+        // another preset, the particles of the old one would look out of place
+        let restyled = config.style.index() != self.frame_data.style;
+        let frame_data = &mut *self.frame_data;
+        frame_data.gravity = config.gravity;
+        frame_data.wind = config.wind;
+        frame_data.turbulence = config.turbulence;
+        frame_data.parallax = config.parallax;
+        frame_data.depth_of_field = config.depth_of_field;
+        frame_data.depth_fade = config.depth_fade;
+        frame_data.max_age = config.max_age;
+        frame_data.melt_rate = config.melt_rate;
+        frame_data.flake_size = config.flake_size;
+        frame_data.style = config.style.index();
+        frame_data.max_speed = config.max_speed;
+        frame_data.drag = config.drag;
+        frame_data.flutter = config.flutter;
+        frame_data.max_spin = config.spin;
+        (frame_data.palette_count, frame_data.palette) = palette(config);
+        frame_data.flake_color = config.flake_color;
+        frame_data.cap_color = config.cap_color;
+        self.frame_data.write(queue);
 
-    pub fn event(&mut self, event: WindowEvent) {
-        tracing::info!("{event:?}");
-        match event {
-            WindowEvent::Occluded(occluded) => self.set_running(!occluded),
-            _ => (),
+        let [r, g, b, a] = config.background.map(f64::from);
+        self.background = wgpu::Color { r, g, b, a };
+
+        if config.max_windows != self.max_windows {
+            tracing::warn!("max_windows only changes after a restart");
+        }
+        if let Err(e) = self.load_sprites(device, queue, config) {
+            tracing::error!("keeping the old sprites: {e}");
+        }
+        if restyled {
+            self.respawn(queue);
+        }
+        if config.particle_count != self.particle_count {
+            self.set_particle_count(device, queue, config.particle_count);
         }
     }
 
+    /// reallocates the instances, the first `count` flakes are kept
+    pub fn set_particle_count(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        count: usize,
+    ) {
+        let kept = self.particle_count.min(count);
+        let spawned = spawn_instances(
+            &mut self.rng, kept..count,
+            self.frame_data.flake_size, self.frame_data.max_spin,
+        );
+        let stride = std::mem::size_of::<SnowflakeInstance>() as u64;
 
-    pub fn redraw(&self) {
-        if self.running {
-            self.fg_window.request_redraw();
+        let instance_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("snow-instance"),
+            usage: self.instance_buffer.usage(),
+            size: stride * count as u64,
+            mapped_at_creation: false,
+        });
+
+        if let Some(instances) = &mut self.cpu_instances {
+            instances.truncate(kept);
+            instances.extend(spawned);
+            queue.write_buffer(&instance_buffer, 0, bytemuck::cast_slice(instances));
+        } else {
+            let mut encoder = device.create_command_encoder(
+                &wgpu::CommandEncoderDescriptor {
+                    label: Some("resize-encoder"),
+                }
+            );
+            encoder.copy_buffer_to_buffer(
+                &self.instance_buffer, 0,
+                &instance_buffer, 0,
+                stride * kept as u64,
+            );
+            queue.write_buffer(&instance_buffer, stride * kept as u64, bytemuck::cast_slice(&spawned));
+            queue.submit(Some(encoder.finish()));
         }
-    }
 
-    pub fn update_windows(&mut self, queue: &wgpu::Queue) {
-        let dim = self.fg_window.inner_size().cast::<f32>();
-        let windows = get_windows();
-        let buf_data = windows.iter()
-            .filter(|v| v.layer == 0)
-            .map(|v| RectInstance {
-                pos: [v.pos.0 as f32 / dim.width, v.pos.1 as f32 / dim.height],
-                dim: [v.dim.0 as f32 / dim.width, v.dim.1 as f32 / dim.height],
-            })
-        .collect::<Vec<_>>();
+        if let (Some(layout), Some(cap_buffers)) = (&self.compute_bind_group_layout, &self.cap_buffers) {
+            self.compute_bind_group = Some(create_compute_bind_group(
+                device, layout,
+                &instance_buffer, &self.window_buffer, cap_buffers,
+            ));
+        }
 
-        queue.write_buffer(
-            &self.window_buffer,
-            0, bytemuck::cast_slice(&buf_data[..buf_data.len().min(self.max_windows)]),
+        tracing::info!("particle count: {} -> {count}", self.particle_count);
+        self.instance_buffer = instance_buffer;
+        self.particle_count = count;
+        self.frame_data.active_count = count as u32;
+    }
+
+    /// replaces every flake with a new one
+    pub fn respawn(&mut self, queue: &wgpu::Queue) {
+        let spawned = spawn_instances(
+            &mut self.rng, 0..self.particle_count,
+            self.frame_data.flake_size, self.frame_data.max_spin,
         );
+        queue.write_buffer(&self.instance_buffer, 0, bytemuck::cast_slice(&spawned));
+        if let Some(instances) = &mut self.cpu_instances {
+            *instances = spawned;
+        }
+    }
 
-        self.windows = windows.into_iter()
-            .filter(|v| v.layer == 0)
-            .map(|v| (v.number, v))
-        .collect();
+    /// only the first `count` flakes are stepped and drawn, the
+    /// others are kept as they are until they are active again
+    pub fn set_active_count(&mut self, count: usize) {
+        self.frame_data.active_count = count.clamp(1, self.particle_count) as u32;
     }
 
-    pub fn update(&mut self, queue: &wgpu::Queue) {
-        self.frame_data.time = self.creation.elapsed().as_secs_f32();
-        self.frame_data.dt = self.last_draw.elapsed().as_secs_f32();
-        self.last_draw = Instant::now();
-        self.update_windows(queue);
+    /// rebuilds the pipelines using `shader` from `src`. on a compile
+    /// error the previous pipelines stay in use
+    pub fn reload_shader(
+        &mut self,
+        device: &wgpu::Device,
+        shader: Shader,
+        src: &str,
+    ) -> Result<(), wgpu::Error> {
+        let layouts = &self.layouts;
+        if shader == Shader::Simulate && layouts.sim.is_none() {
+            tracing::info!("not simulating on the gpu, ignoring {}", shader.file_name());
+            return Ok(());
+        }
+
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let module = shader.create_module(device, src);
+        let render = |label, layout, buffers: &[wgpu::VertexBufferLayout]| {
+            create_render_pipeline(device, label, layout, &module, buffers, self.format)
+        };
+        let reloaded = match shader {
+            Shader::Render => Reloaded::Render(render(
+                "render pipeline", &layouts.render,
+                &[SnowflakeVertex::desc(), SnowflakeInstance::desc()],
+            )),
+            Shader::Rect => Reloaded::Rect(render(
+                "rect pipeline", &layouts.rect,
+                &[SnowflakeVertex::desc(), RectInstance::desc()],
+            )),
+            Shader::Cap => Reloaded::Cap(render(
+                "cap pipeline", &layouts.cap,
+                &[RectInstance::desc()],
+            )),
+            Shader::Simulate => {
+                let layout = layouts.sim.as_ref().expect("checked above");
+                let (sim, melt) = create_sim_pipelines(device, layout, &module);
+                Reloaded::Simulate(sim, melt)
+            },
+        };
+        if let Some(e) = pollster::block_on(device.pop_error_scope()) {
+            return Err(e);
+        }
+
+        match reloaded {
+            Reloaded::Render(v) => self.render_pipeline = v,
+            Reloaded::Rect(v) => self.rect_pipeline = v,
+            Reloaded::Cap(v) => self.cap_pipeline = v,
+            Reloaded::Simulate(sim, melt) => {
+                self.sim_pipeline = Some(sim);
+                self.melt_pipeline = Some(melt);
+            },
+        }
+        tracing::info!("reloaded {}", shader.file_name());
+        Ok(())
     }
 
-    pub fn render(
+    pub fn frame_data(&self) -> &FrameData { &self.frame_data }
+    pub fn frame_data_mut(&mut self) -> &mut FrameData { &mut self.frame_data }
+
+    pub fn set_draw_windows(&mut self, v: bool) { self.draw_windows = v }
+
+    /// draws the flakes from `sheet`, or as crystals without one
+    pub fn set_sprites(
         &mut self,
         device: &wgpu::Device,
         queue: &wgpu::Queue,
-    ) -> Result<(), wgpu::SurfaceError> {
+        sheet: Option<&SpriteSheet>,
+    ) -> Result<(), SpriteError> {
+        if let Some(sheet) = sheet {
+            sheet.check_size(&device.limits())?;
+        }
+        self.sprites = create_sprites(device, &self.sprite_bind_group_layout, sheet.map(|v| (queue, v)));
+        Ok(())
+    }
+
+    /// loads the sprite sheet of `config` unless it already is
+    pub fn load_sprites(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        config: &SnowConfig,
+    ) -> Result<(), BuildError> {
+        let source = config.sprite_sheet.clone().map(|v| (v, config.sprite_grid));
+        if source == self.sprites.source {
+            return Ok(());
+        }
+        let sheet = SpriteSheet::for_config(config)?;
+        self.set_sprites(device, queue, sheet.as_ref())?;
+        Ok(())
+    }
+
+    /// another view of the particles, e.g. one monitor of a shared snow
+    pub fn create_view(&self, device: &wgpu::Device, data: ViewData) -> View {
+        create_view(device, &self.uniform_bind_group_layout, &self.frame_data, data)
+    }
+
+    pub fn write_frame_data(&self, queue: &wgpu::Queue) {
         self.frame_data.write(queue);
+    }
 
-        let fg_output = self.fg_surface.get_current_texture()?;
-        let fg_view = fg_output.texture.create_view(
-            &wgpu::TextureViewDescriptor::default(),
-        );
+    /// uploads everything the next `encode` needs,
+    /// stepping the particles here when simulating on the cpu
+    pub fn prepare(&mut self, queue: &wgpu::Queue) {
+        self.write_frame_data(queue);
+        if let (Some(instances), Some(caps)) = (&mut self.cpu_instances, &mut self.cpu_caps) {
+            let active = &mut instances[..self.frame_data.active_count as usize];
+            cpu::step(active, &self.frame_data, &self.window_rects, caps);
+            cpu::melt(caps, &self.frame_data);
+            queue.write_buffer(&self.instance_buffer, 0, bytemuck::cast_slice(active));
+            queue.write_texture(
+                self.cap_texture.as_image_copy(),
+                bytemuck::cast_slice(&caps.heights),
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(CAP_COLUMNS as u32 * 4),
+                    rows_per_image: None,
+                },
+                self.cap_texture.size(),
+            );
+        }
+    }
 
-        let mut encoder = device.create_command_encoder(
-            &wgpu::CommandEncoderDescriptor {
-                label: Some("win-encoder"),
+    /// copies the current instances back from the gpu
+    pub fn read_instances(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> Vec<SnowflakeInstance> {
+        read_buffer(device, queue, &self.instance_buffer)
+    }
+
+    /// the current cap heightfields, see `cpu::Caps`
+    pub fn read_caps(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> Vec<u32> {
+        match (&self.cap_buffers, &self.cpu_caps) {
+            (Some((heights, ..)), _) => read_buffer(device, queue, heights),
+            (_, Some(caps)) => caps.heights.clone(),
+            _ => unreachable!("caps are either on the gpu or the cpu"),
+        }
+    }
+
+    /// the snow of the given caps falls off as flakes in the next step,
+    /// e.g. when their window moved. replaces the ones of the last call
+    pub fn shed_caps(&mut self, queue: &wgpu::Queue, caps: &[ShedCap]) {
+        let caps = &caps[..caps.len().min(self.max_windows)];
+        if let Some((_, _, shed)) = &self.cap_buffers {
+            if !caps.is_empty() || self.shedding {
+                let mut slots = vec![ShedCap::zeroed(); self.max_windows];
+                slots[..caps.len()].copy_from_slice(caps);
+                queue.write_buffer(shed, 0, bytemuck::cast_slice(&slots));
             }
+        }
+        self.shedding = !caps.is_empty();
+        if let Some(cpu_caps) = &mut self.cpu_caps {
+            cpu_caps.shed = caps.to_vec();
+        }
+    }
+
+    pub fn max_windows(&self) -> usize { self.max_windows }
+
+    /// uploads the window rects, sorted front to back.
+    /// everything past `max_windows` is dropped
+    pub fn write_windows(&mut self, queue: &wgpu::Queue, rects: &[RectInstance]) {
+        self.window_count = rects.len().min(self.max_windows);
+        self.frame_data.window_count = self.window_count as u32;
+        queue.write_buffer(
+            &self.window_buffer,
+            0, bytemuck::cast_slice(&rects[..self.window_count]),
         );
+        if self.cpu_instances.is_some() {
+            self.window_rects = rects[..self.window_count].to_vec();
+        }
+    }
 
-        {
+    /// records one simulation step followed by drawing
+    /// the particles into `target`
+    pub fn encode(&self, encoder: &mut wgpu::CommandEncoder, target: &wgpu::TextureView) {
+        self.encode_step(encoder);
+        self.encode_draw(encoder, target, &self.view);
+    }
+
+    /// records one simulation step, after `prepare`
+    pub fn encode_step(&self, encoder: &mut wgpu::CommandEncoder) {
+        if let (Some(sim_pipeline), Some(compute_bind_group)) = (&self.sim_pipeline, &self.compute_bind_group) {
             let mut sim_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
                 label: Some("sim pass"),
                 timestamp_writes: None,
             });
 
-            sim_pass.set_pipeline(&self.sim_pipeline);
-            sim_pass.set_bind_group(0, &self.uniform_bind_group, &[]);
-            sim_pass.set_bind_group(1, &self.compute_bind_group, &[]);
+            sim_pass.set_pipeline(sim_pipeline);
+            sim_pass.set_bind_group(0, &self.view.bind_group, &[]);
+            sim_pass.set_bind_group(1, compute_bind_group, &[]);
             #[cfg(debug_assertions)]
             sim_pass.insert_debug_marker("sim pass update");
 
             let compute_size: usize = 256;
-            let n_instances = self.particle_count.div_ceil(compute_size);
+            let n_instances = self.active_count().div_ceil(compute_size);
             sim_pass.dispatch_workgroups(n_instances as _, 1, 1);
+
+            if let Some(melt_pipeline) = &self.melt_pipeline {
+                sim_pass.set_pipeline(melt_pipeline);
+                let n_cells = (self.max_windows + 1) * CAP_COLUMNS;
+                sim_pass.dispatch_workgroups(n_cells.div_ceil(compute_size) as _, 1, 1);
+            }
         }
 
+        if let Some((heights, ..)) = &self.cap_buffers {
+            encoder.copy_buffer_to_texture(
+                wgpu::ImageCopyBuffer {
+                    buffer: heights,
+                    layout: wgpu::ImageDataLayout {
+                        offset: 0,
+                        bytes_per_row: Some(CAP_COLUMNS as u32 * 4),
+                        rows_per_image: None,
+                    },
+                },
+                self.cap_texture.as_image_copy(),
+                self.cap_texture.size(),
+            );
+        }
+    }
+
+    /// records drawing what `view` shows of the particles into `target`
+    pub fn encode_draw(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        target: &wgpu::TextureView,
+        view: &View,
+    ) {
         {
             let mut renderpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 label: Some("fg-renderpass"),
                 color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &fg_view,
+                    view: target,
                     resolve_target: None,
                     ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color {
-                            // r: self.creation.elapsed().as_secs_f64().sin().abs(),
-                            r: 0.0,
-                            g: 0.2,
-                            b: 0.3, a: 0.0,
-                        }),
+                        load: wgpu::LoadOp::Clear(self.background),
                         store: wgpu::StoreOp::Store,
                     },
 
@@ -486,116 +1106,779 @@ impl SnowState {
                 timestamp_writes: None,
             });
 
-            // renderpass.set_pipeline(&self.rect_pipeline);
-            // renderpass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
-            // renderpass.set_vertex_buffer(1, self.window_buffer.slice(..));
-            // renderpass.draw(0..(self.vertex_count as _), 0..(self.windows.len() as _));
+            if self.draw_windows {
+                renderpass.set_pipeline(&self.rect_pipeline);
+                renderpass.set_bind_group(0, &view.bind_group, &[]);
+                renderpass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+                renderpass.set_vertex_buffer(1, self.window_buffer.slice(..));
+                renderpass.draw(0..(self.vertex_count as _), 0..(self.window_count as _));
+            }
+
+            // two triangles for every column of a cap
+            let cap_vertices = CAP_COLUMNS as u32 * 6;
+            renderpass.set_pipeline(&self.cap_pipeline);
+            renderpass.set_bind_group(0, &view.bind_group, &[]);
+            renderpass.set_bind_group(1, &self.cap_bind_group, &[]);
+            renderpass.set_vertex_buffer(0, self.window_buffer.slice(..));
+            renderpass.draw(0..cap_vertices, 0..(self.window_count as _));
+            renderpass.set_vertex_buffer(0, self.bottom_buffer.slice(..));
+            renderpass.draw(0..cap_vertices, 0..1);
 
             renderpass.set_pipeline(&self.render_pipeline);
-            renderpass.set_bind_group(0, &self.uniform_bind_group, &[]);
+            renderpass.set_bind_group(0, &view.bind_group, &[]);
+            renderpass.set_bind_group(1, &self.sprites.bind_group, &[]);
             renderpass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
             renderpass.set_vertex_buffer(1, self.instance_buffer.slice(..));
-            renderpass.draw(0..(self.vertex_count as _), 0..(self.particle_count as _));
+            renderpass.draw(0..(self.vertex_count as _), 0..(self.active_count() as _));
+        }
+    }
+}
+
+impl Simulation {
+    pub fn new(
+        device: &wgpu::Device,
+        snow: Snow,
+        config: &SnowConfig,
+        seed: u64,
+        region: Region,
+        window_source: Box<dyn WindowSource>,
+        cursor_source: Box<dyn CursorSource>,
+    ) -> Self {
+        let cap_slots = CapSlots::new(snow.max_windows() as u32);
+        let budget = Budget::new(config, snow.active_count(), 0.0);
+        Self {
+            snow, region,
+            creation: Instant::now(),
+            last_draw: Instant::now(),
+            clock: FrameClock::default(),
+            wind: Wind::new(config, seed),
+            windows: HashMap::new(),
+            window_source, cap_slots,
+            cursor_source,
+            cursor_motion: CursorMotion::default(),
+            budget,
+            frame_interval: 0.0,
+            work: Duration::ZERO,
+            timer: GpuTimer::new(device),
+        }
+    }
+
+    pub fn snow(&self) -> &Snow { &self.snow }
+    pub fn snow_mut(&mut self) -> &mut Snow { &mut self.snow }
+    pub fn region(&self) -> Region { self.region }
+
+    /// `Snow::apply_config`, the budget starts over with the new bounds
+    pub fn apply_config(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        config: &SnowConfig,
+    ) {
+        self.snow.apply_config(device, queue, config);
+        self.wind.set_config(config);
+        self.budget = Budget::new(config, self.snow.active_count(), self.frame_interval);
+        let active = self.budget.as_ref().map_or(config.particle_count, |v| v.active());
+        self.snow.set_active_count(active);
+    }
+
+    /// `Snow::set_particle_count`, which is also the most the budget allows
+    pub fn set_particle_count(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        count: usize,
+    ) {
+        self.snow.set_particle_count(device, queue, count);
+        if let Some(budget) = &mut self.budget {
+            budget.set_max(count);
+        }
+    }
+
+    /// the flakes keep their place in simulation space
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+        self.snow.frame_data_mut().aspect = region.aspect();
+    }
+
+    /// a view of `region`, which should be inside of the simulated one
+    pub fn create_view(&self, device: &wgpu::Device, region: Region) -> View {
+        self.snow.create_view(device, ViewData::new(region, self.region))
+    }
+
+    /// frames are paced this far apart, which the budget doesn't count as load
+    pub fn set_frame_interval(&mut self, interval: Duration) {
+        self.frame_interval = interval.as_secs_f32();
+        if let Some(budget) = &mut self.budget {
+            budget.set_interval(self.frame_interval);
+        }
+    }
+
+    /// don't step over the time spent paused
+    pub fn resume(&mut self) {
+        self.last_draw = Instant::now();
+        self.clock.reset();
+    }
+
+    /// starts over with a new snow, the old one was on a lost device
+    pub fn recreate(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        adapter: &wgpu::Adapter,
+        format: wgpu::TextureFormat,
+        config: &SnowConfig,
+        seed: u64,
+    ) {
+        self.snow = Snow::new(
+            device, format,
+            config, self.region.aspect(),
+            seed, SimBackend::for_adapter(adapter),
+        );
+        if let Err(e) = self.snow.load_sprites(device, queue, config) {
+            tracing::error!("drawing crystals instead of sprites: {e}");
+        }
+        self.cap_slots = CapSlots::new(self.snow.max_windows() as u32);
+        self.wind = Wind::new(config, seed);
+        self.budget = Budget::new(config, self.snow.active_count(), self.frame_interval);
+        self.timer = GpuTimer::new(device);
+        self.resume();
+    }
+
+    pub fn update(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        let start = Instant::now();
+        let work = std::mem::take(&mut self.work).as_secs_f32();
+        let elapsed = self.last_draw.elapsed().as_secs_f32();
+        let frame_data = self.snow.frame_data_mut();
+        frame_data.time = self.creation.elapsed().as_secs_f32();
+        // flakes shouldn't jump after a stall
+        frame_data.dt = self.clock.step(elapsed);
+        (frame_data.wind, frame_data.gust) = self.wind.sample(frame_data.time);
+        self.last_draw = Instant::now();
+
+        if let Some(budget) = &mut self.budget {
+            let gpu = self.timer.as_mut().and_then(|v| v.read(device, queue));
+            let active = budget.update(elapsed, work, gpu);
+            if active != self.snow.active_count() {
+                tracing::debug!("active particles: {active}");
+                self.snow.set_active_count(active);
+            }
+        }
+        self.update_windows(queue);
+        self.update_cursor();
+        self.work = start.elapsed();
+    }
+
+    /// records a step and drawing all of it into `target`
+    pub fn encode(
+        &mut self,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        target: &wgpu::TextureView,
+    ) {
+        let start = Instant::now();
+        self.snow.prepare(queue);
+        if let Some(timer) = &mut self.timer { timer.begin(encoder) }
+        self.snow.encode(encoder, target);
+        if let Some(timer) = &mut self.timer { timer.end(encoder) }
+        self.work += start.elapsed();
+    }
+
+    /// after the encoder of `encode` was submitted
+    pub fn submitted(&mut self) {
+        if let Some(timer) = &mut self.timer { timer.submitted() }
+    }
+
+    /// steps the particles on their own, for a snow that is only drawn
+    /// through views. `update` has to be called before
+    pub fn step(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        let start = Instant::now();
+        self.snow.prepare(queue);
+        let mut encoder = device.create_command_encoder(
+            &wgpu::CommandEncoderDescriptor {
+                label: Some("step-encoder"),
+            }
+        );
+        if let Some(timer) = &mut self.timer { timer.begin(&mut encoder) }
+        self.snow.encode_step(&mut encoder);
+        if let Some(timer) = &mut self.timer { timer.end(&mut encoder) }
+        queue.submit(Some(encoder.finish()));
+        self.submitted();
+        self.work += start.elapsed();
+    }
+
+    fn update_windows(&mut self, queue: &wgpu::Queue) {
+        let windows = self.window_source.windows().into_iter()
+            .filter(|v| v.layer == 0)
+            .take(self.snow.max_windows())
+        .collect::<Vec<_>>();
+
+        let (caps, shed) = self.cap_slots.update(&windows);
+        let dt = self.snow.frame_data().dt;
+        let shed = shed.into_iter()
+            .map(|v| {
+                let (pos, dim) = self.region.to_unit(&v.from);
+                // thrown along with a moving window
+                let vel = match &v.to {
+                    Some(to) if dt > 0.0 => {
+                        let (from, to) = (self.region.to_sim(v.from.pos), self.region.to_sim(to.pos));
+                        [(to[0] - from[0]) / dt, (to[1] - from[1]) / dt]
+                    },
+                    _ => [0.0, 0.0],
+                };
+                ShedCap { pos, dim, vel, cap: v.row, _padding: 0 }
+            })
+        .collect::<Vec<_>>();
+        self.snow.shed_caps(queue, &shed);
+
+        // windows outside of the region end up outside [0, 1]
+        let buf_data = windows.iter()
+            .zip(caps)
+            .map(|(v, cap)| {
+                let (pos, dim) = self.region.to_unit(v);
+                RectInstance { pos, dim, cap, _padding: 0 }
+            })
+        .collect::<Vec<_>>();
+
+        self.snow.write_windows(queue, &buf_data);
+
+        self.windows = windows.into_iter()
+            .map(|v| (v.number, v))
+        .collect();
+    }
+
+    fn update_cursor(&mut self) {
+        // like the windows, outside of [-1, 1] when outside of the region
+        let cursor = self.cursor_source.position().map(|v| self.region.to_sim(v));
+
+        let frame_data = self.snow.frame_data_mut();
+        frame_data.cursor_vel = self.cursor_motion.update(cursor, frame_data.dt);
+        if let Some(cursor) = cursor {
+            frame_data.cursor = cursor;
+        }
+    }
+}
+
+impl SnowState {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new<E>(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        instance: &wgpu::Instance,
+        adapter: &wgpu::Adapter,
+
+        config: &SnowConfig,
+        vsync: bool,
+        seed: u64,
+        monitor: Monitor,
+        window_source: Box<dyn WindowSource>,
+        cursor_source: Box<dyn CursorSource>,
+        event_loop: &EventLoopWindowTarget<E>,
+    ) -> Result<Self, BuildError> {
+        let fg_window = WindowBuilder::new()
+            .with_title("snow-fg")
+            .with_transparent(true)
+            .with_decorations(false)
+            .with_window_level(WindowLevel::AlwaysOnTop)
+        .build(event_loop)?;
+
+        fg_window.set_cursor_hittest(false)?;
+        platform::configure_window(&fg_window, &monitor);
+        
+        let size = fg_window.inner_size();
+        let region = window_region(&fg_window, size);
+
+        let fg_surface = unsafe { instance.create_surface(&fg_window) }?;
+        let fg_config = surface_config(&fg_surface, adapter, size, vsync);
+        fg_surface.configure(device, &fg_config);
+
+        let mut snow = Snow::new(
+            device, fg_config.format,
+            config, region.aspect(),
+            seed, SimBackend::for_adapter(adapter),
+        );
+        snow.load_sprites(device, queue, config)?;
+        let sim = Simulation::new(device, snow, config, seed, region, window_source, cursor_source);
+
+        // info: maybe set to false?
+        let running = true;
+
+        Ok(Self {
+            sim: Sim::Own(Box::new(sim)),
+            fg_surface, fg_config,
+            fg_window, size, monitor,
+            running,
+        })
+    }
+
+    pub fn window_id(&self) -> WindowId { self.fg_window.id() }
+    pub fn monitor(&self) -> &Monitor { &self.monitor }
+    pub fn size(&self) -> PhysicalSize<u32> { self.size }
+
+    /// the part of the desktop this monitor covers
+    pub fn region(&self) -> Region {
+        window_region(&self.fg_window, self.size)
+    }
+
+    /// the monitor after a screen configuration change, the window
+    /// follows a new resolution and `resize` is called from its event
+    pub fn set_monitor(&mut self, monitor: Monitor) {
+        if platform::monitor_frame(&monitor) != platform::monitor_frame(&self.monitor) {
+            tracing::info!("monitor changed: {:?}", platform::monitor_frame(&monitor));
+            platform::configure_window(&self.fg_window, &monitor);
+        }
+        self.monitor = monitor;
+    }
+
+    /// draws its part of `shared` from now on and drops its own snow. if
+    /// there is no shared snow yet, its own becomes the shared one
+    pub fn share(&mut self, device: &wgpu::Device, shared: &mut Option<Simulation>) {
+        let view = match (&*shared, &self.sim) {
+            (_, Sim::Shared(_)) => return,
+            (Some(shared), _) => shared.create_view(device, self.region()),
+            (None, Sim::Own(own)) => own.create_view(device, self.region()),
+        };
+        if let Sim::Own(own) = std::mem::replace(&mut self.sim, Sim::Shared(view)) {
+            shared.get_or_insert(*own);
+        }
+    }
+
+    /// follows a change of this monitor or the region of `shared`
+    pub fn update_view(&mut self, queue: &wgpu::Queue, shared: &Simulation) {
+        let region = self.region();
+        if let Sim::Shared(view) = &mut self.sim {
+            view.set(queue, ViewData::new(region, shared.region()));
+        }
+    }
+
+    /// rebuilds everything on a new device after the old one was lost.
+    /// the flakes and caps were on the old device, so they start over.
+    /// a shared snow has to be recreated before
+    #[allow(clippy::too_many_arguments)]
+    pub fn recreate(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        adapter: &wgpu::Adapter,
+        config: &SnowConfig,
+        vsync: bool,
+        seed: u64,
+        shared: Option<&Simulation>,
+    ) {
+        self.fg_config = surface_config(&self.fg_surface, adapter, self.size, vsync);
+        self.fg_surface.configure(device, &self.fg_config);
+
+        let region = self.region();
+        match (&mut self.sim, shared) {
+            (Sim::Own(sim), _) => sim.recreate(device, queue, adapter, self.fg_config.format, config, seed),
+            (Sim::Shared(view), Some(shared)) => *view = shared.create_view(device, region),
+            (Sim::Shared(_), None) => (),
+        }
+    }
+
+    /// switches the present mode, if the surface supports it
+    pub fn set_vsync(&mut self, device: &wgpu::Device, adapter: &wgpu::Adapter, vsync: bool) {
+        let caps = self.fg_surface.get_capabilities(adapter);
+        let present_mode = pacing::present_mode(vsync, &caps.present_modes);
+        if present_mode == self.fg_config.present_mode { return }
+        tracing::info!("present mode: {present_mode:?}");
+        self.fg_config.present_mode = present_mode;
+        self.fg_surface.configure(device, &self.fg_config);
+    }
+
+    /// after `SurfaceError::Lost` or `Outdated`
+    pub fn reconfigure(&mut self, device: &wgpu::Device) {
+        self.resize(device, self.fg_window.inner_size());
+    }
+
+    /// reconfigures the surface, the simulation space stays the same
+    pub fn resize(&mut self, device: &wgpu::Device, size: PhysicalSize<u32>) {
+        // minimized or in the middle of a mode switch
+        if size.width == 0 || size.height == 0 { return }
+
+        tracing::info!("resize: {}x{}", size.width, size.height);
+        self.size = size;
+        self.fg_config.width = size.width;
+        self.fg_config.height = size.height;
+        self.fg_surface.configure(device, &self.fg_config);
+        if let Sim::Own(sim) = &mut self.sim {
+            sim.set_region(window_region(&self.fg_window, self.size));
+        }
+    }
+
+    pub fn set_running(&mut self, v: bool) {
+        tracing::info!("set running: {v}");
+        self.running = v;
+        if v {
+            if let Sim::Own(sim) = &mut self.sim {
+                sim.resume();
+            }
+            self.redraw();
+        }
+    }
+
+    pub fn running(&self) -> bool { self.running }
+
+    /// its own snow, `None` when drawing a shared one
+    pub fn snow(&self) -> Option<&Snow> {
+        match &self.sim {
+            Sim::Own(sim) => Some(sim.snow()),
+            Sim::Shared(_) => None,
+        }
+    }
+
+    pub fn sim_mut(&mut self) -> Option<&mut Simulation> {
+        match &mut self.sim {
+            Sim::Own(sim) => Some(sim),
+            Sim::Shared(_) => None,
+        }
+    }
+
+    pub fn event(&mut self, event: WindowEvent) {
+        tracing::info!("{event:?}");
+        if let WindowEvent::Occluded(occluded) = event {
+            self.set_running(!occluded);
+        }
+    }
+
+
+    pub fn redraw(&self) {
+        if self.running {
+            self.fg_window.request_redraw();
+        }
+    }
+
+    /// steps its own snow, a shared one is stepped by its owner
+    pub fn update(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        if let Sim::Own(sim) = &mut self.sim {
+            // the window might have moved
+            sim.set_region(window_region(&self.fg_window, self.size));
+            sim.update(device, queue);
+        }
+    }
+
+    pub fn render(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        shared: Option<&Simulation>,
+    ) -> Result<(), wgpu::SurfaceError> {
+        if let (Sim::Shared(_), None) = (&self.sim, shared) {
+            return Ok(());
+        }
+
+        let fg_output = self.fg_surface.get_current_texture()?;
+        let fg_view = fg_output.texture.create_view(
+            &wgpu::TextureViewDescriptor::default(),
+        );
+
+        let mut encoder = device.create_command_encoder(
+            &wgpu::CommandEncoderDescriptor {
+                label: Some("win-encoder"),
+            }
+        );
+
+        match (&mut self.sim, shared) {
+            (Sim::Own(sim), _) => sim.encode(queue, &mut encoder, &fg_view),
+            (Sim::Shared(view), Some(shared)) => shared.snow().encode_draw(&mut encoder, &fg_view, view),
+            (Sim::Shared(_), None) => (),
         }
         queue.submit(Some(encoder.finish()));
+        if let Sim::Own(sim) = &mut self.sim {
+            sim.submitted();
+        }
         fg_output.present();
         Ok(())
     }
 }
 
-fn configure_window(window: &Window, monitor: Id<NSScreen>) {
-    let ns_view = match window.raw_window_handle() {
-        RawWindowHandle::AppKit(handle) => unsafe {
-            Id::new(handle.ns_view as *mut NSView)
-                .expect("could not get ns_view")
+/// the region of `window`, in the coordinates of the window source
+fn window_region(window: &Window, size: PhysicalSize<u32>) -> Region {
+    let scale = platform::window_scale(window);
+    let origin = window.outer_position().unwrap_or_default().cast::<f64>();
+    Region {
+        origin: (origin.x / scale, origin.y / scale),
+        dim: (size.width as f64 / scale, size.height as f64 / scale),
+    }
+}
+
+/// the surface setup for `size`, preferring an srgb format
+fn surface_config(
+    surface: &wgpu::Surface,
+    adapter: &wgpu::Adapter,
+    size: PhysicalSize<u32>,
+    vsync: bool,
+) -> wgpu::SurfaceConfiguration {
+    let caps = surface.get_capabilities(adapter);
+    let format = caps.formats.iter()
+        .copied()
+        .find(|f| f.is_srgb())
+    .unwrap_or(caps.formats[0]);
+
+    wgpu::SurfaceConfiguration {
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+        format,
+        width: size.width,
+        height: size.height,
+        present_mode: pacing::present_mode(vsync, &caps.present_modes),
+        alpha_mode: wgpu::CompositeAlphaMode::PostMultiplied,
+        view_formats: vec![],
+    }
+}
+
+enum Reloaded {
+    Render(wgpu::RenderPipeline),
+    Rect(wgpu::RenderPipeline),
+    Cap(wgpu::RenderPipeline),
+    Simulate(wgpu::ComputePipeline, wgpu::ComputePipeline),
+}
+
+/// the render pipelines only differ in their shader and vertex buffers
+fn create_render_pipeline(
+    device: &wgpu::Device,
+    label: &str,
+    layout: &wgpu::PipelineLayout,
+    module: &wgpu::ShaderModule,
+    buffers: &[wgpu::VertexBufferLayout],
+    format: wgpu::TextureFormat,
+) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(label),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module,
+            buffers,
+            entry_point: "vertex_main",
         },
-        v => panic!("invalid window handle type: {v:?}"),
-    };
-    let ns_window = ns_view.window().expect("could not get ns_window");
-
-    ns_window.setMovable(false);
-    ns_window.setFrame_display(monitor.frame(), true);
-    // disable window shadow to remove artifacts
-    ns_window.setHasShadow(false);
-    // ns_window.setLevel(99999);
-    // ns_window.setLevel(-1);
-
-    unsafe {
-        ns_window.setCollectionBehavior(
-            0
-            // moves with current space + can overlay fullscreen windows
-            |  AppKit::NSWindowCollectionBehaviorCanJoinAllSpaces
-            // cant tab to window
-            | AppKit::NSWindowCollectionBehaviorIgnoresCycle
-            // stays even while mission control
-            | AppKit::NSWindowCollectionBehaviorStationary
-            // dont show in fullscreen
-            | AppKit::NSWindowCollectionBehaviorFullScreenNone
-        );
-    };
+        fragment: Some(wgpu::FragmentState {
+            module,
+            entry_point: "fragment_main",
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: None,
+            polygon_mode: wgpu::PolygonMode::Fill,
+            unclipped_depth: false,
+            conservative: false,
+        },
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState {
+            count: 1,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+        multiview: None,
+    })
+}
 
-    std::mem::forget(ns_view);
+/// stepping the flakes and melting the caps
+fn create_sim_pipelines(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    module: &wgpu::ShaderModule,
+) -> (wgpu::ComputePipeline, wgpu::ComputePipeline) {
+    let sim = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+        label: Some("sim pipeline"),
+        layout: Some(layout),
+        module,
+        entry_point: "main",
+    });
+    let melt = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+        label: Some("melt pipeline"),
+        layout: Some(layout),
+        module,
+        entry_point: "melt",
+    });
+    (sim, melt)
 }
 
-#[derive(Debug)]
-struct AppWindow {
-    owner_name: Option<String>,
-    name: Option<String>,
-    pos: (f64, f64),
-    dim: (f64, f64),
-    layer: i64,
-    number: i64,
+/// new flakes anywhere on screen, the index decides the rng stream
+fn spawn_instances(
+    rng: &mut StdRng,
+    indices: std::ops::Range<usize>,
+    flake_size: [f32; 2],
+    max_spin: f32,
+) -> Vec<SnowflakeInstance> {
+    let [min_size, max_size] = flake_size;
+    // one stream for all instances, so an added draw moves every flake after
+    // it and the goldens with them. new attributes derive from the draws here
+    indices.map(|i| {
+        let pos = [
+            rng.gen_range(-1.0..1.0),
+            rng.gen_range(-1.0..1.0) * (1.0 + 0.05),
+        ];
+
+        SnowflakeInstance {
+            pos,
+            vel: [0.0, 0.0],
+            scale: rng.gen_range(min_size..=max_size),
+            age: 0.0,
+            // the stream has to be odd
+            rng: [rng.gen(), (i as u32) << 1 | 1],
+            angle: rng.gen_range(0.0..std::f32::consts::TAU),
+            spin: rng.gen_range(-max_spin..=max_spin),
+            shape: rng.gen(),
+            sprite: rng.gen(),
+            // more far flakes than near ones
+            depth: rng.gen::<f32>().sqrt(),
+            _padding: 0,
+        }
+    }).collect()
 }
 
-fn get_windows() -> Vec<AppWindow> {
-    use core_graphics::window;
-    let arr = unsafe {
-        cf_array::<NSDictionary<NSString, NSObject>>(window::CGWindowListCopyWindowInfo(window::kCGWindowListOptionOnScreenOnly, 0))
+/// uploads `sheet`, or a blank texture that is never sampled
+fn create_sprites(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    sheet: Option<(&wgpu::Queue, &SpriteSheet)>,
+) -> Sprites {
+    let (width, height) = sheet.map_or((1, 1), |(_, v)| (v.width, v.height));
+    let descriptor = wgpu::TextureDescriptor {
+        label: Some("sprite sheet"),
+        size: wgpu::Extent3d { width, height, depth_or_array_layers: 1 },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: wgpu::TextureFormat::Rgba8UnormSrgb,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        view_formats: &[],
+    };
+    let texture = match sheet {
+        Some((queue, sheet)) => device.create_texture_with_data(queue, &descriptor, &sheet.data),
+        None => device.create_texture(&descriptor),
     };
+    let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+        label: Some("sprite sampler"),
+        mag_filter: wgpu::FilterMode::Linear,
+        min_filter: wgpu::FilterMode::Linear,
+        ..Default::default()
+    });
+    let data = UniformBuffer::new(device, SpriteData {
+        grid: sheet.map_or([1, 1], |(_, v)| v.grid),
+        count: sheet.map_or(0, |(_, v)| v.count()),
+        _padding: 0,
+    }, Some("sprite data"));
+
+    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("sprite bind group"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(
+                    &texture.create_view(&wgpu::TextureViewDescriptor::default()),
+                ),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(&sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: data.buffer().as_entire_binding(),
+            },
+        ],
+    });
+    Sprites {
+        _texture: texture,
+        data, bind_group,
+        source: sheet.and_then(|(_, v)| Some((v.path.clone()?, v.grid))),
+    }
+}
 
-    arr.into_iter().map(|v| {
-        autoreleasepool(|p| {
-            let owner_name = v.get(ns_string!("kCGWindowOwnerName")).map(|v| {
-                let v: &NSString = unsafe { std::mem::transmute(v) };
-                v.as_str(p).to_string()
-            });
-            let name = v.get(ns_string!("kCGWindowName")).map(|v| {
-                let v: &NSString = unsafe { std::mem::transmute(v) };
-                v.as_str(p).to_string()
-            });
-            let bounds: &NSDictionary<NSString, NSNumber> = unsafe {
-                std::mem::transmute(v.get(ns_string!("kCGWindowBounds")).unwrap())
-            };
-            let (x, y, w, h) = (
-                bounds[ns_string!("X")].as_f64(),
-                bounds[ns_string!("Y")].as_f64(),
-                bounds[ns_string!("Width")].as_f64(),
-                bounds[ns_string!("Height")].as_f64(),
-            );
-            let layer: &NSNumber = unsafe {
-                std::mem::transmute(v.get(ns_string!("kCGWindowLayer")).unwrap())
-            };
-            let layer = layer.as_i64();
-            let number: &NSNumber = unsafe {
-                std::mem::transmute(v.get(ns_string!("kCGWindowNumber")).unwrap())
-            };
-            let number = number.as_i64();
-            AppWindow {
-                owner_name, layer, name,
-                dim: (w, h),
-                pos: (x, y),
-                number,
-            }
-        })
-    }).collect()
+fn create_view(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    frame_data: &UniformBuffer<FrameData>,
+    data: ViewData,
+) -> View {
+    let data = UniformBuffer::new(device, data, Some("view data"));
+    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("uniform bind group"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: frame_data.buffer().as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: data.buffer().as_entire_binding(),
+            },
+        ],
+    });
+    View { data, bind_group }
 }
 
-unsafe fn cf_array<T: Message>(array: core_graphics::display::CFArrayRef) -> Vec<Id<T>> {
-    (0..core_graphics::display::CFArrayGetCount(array)).flat_map(|i| {
-        let unmanaged = core_graphics::display::CFArrayGetValueAtIndex(array, i);
-        if unmanaged.is_null() { tracing::warn!("got null from cf_array") }
-        let rec = std::mem::transmute(unmanaged);
-        Id::new(rec)
-    }).collect()
+fn create_compute_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    instance_buffer: &wgpu::Buffer,
+    window_buffer: &wgpu::Buffer,
+    (height_buffer, pending_buffer, shed_buffer): &(wgpu::Buffer, wgpu::Buffer, wgpu::Buffer),
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("compute bind group"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: instance_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: window_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: height_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: pending_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 4,
+                resource: shed_buffer.as_entire_binding(),
+            },
+        ]
+    })
 }
 
+/// copies `buffer` back from the gpu, it needs `COPY_SRC`
+fn read_buffer<T: Pod>(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    buffer: &wgpu::Buffer,
+) -> Vec<T> {
+    let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("readback"),
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        size: buffer.size(),
+        mapped_at_creation: false,
+    });
+
+    let mut encoder = device.create_command_encoder(
+        &wgpu::CommandEncoderDescriptor {
+            label: Some("readback-encoder"),
+        }
+    );
+    encoder.copy_buffer_to_buffer(buffer, 0, &readback_buffer, 0, buffer.size());
+    queue.submit(Some(encoder.finish()));
+
+    let slice = readback_buffer.slice(..);
+    slice.map_async(wgpu::MapMode::Read, |res| {
+        if let Err(e) = res { tracing::error!("could not map readback buffer: {e}") }
+    });
+    device.poll(wgpu::Maintain::Wait);
+
+    let data = bytemuck::cast_slice(&slice.get_mapped_range()).to_vec();
+    readback_buffer.unmap();
+    data
+}
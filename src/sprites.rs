@@ -0,0 +1,172 @@
+//! sprite sheets: a png split into a grid of equally sized sprites that
+//! the flakes are drawn as instead of crystals, e.g. leaves or logos
+
+use std::{io::Read, path::{Path, PathBuf}};
+
+use crate::config::SnowConfig;
+
+
+#[derive(Debug, thiserror::Error)]
+pub enum SpriteError {
+    #[error("could not read {path:?}: {source}")]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[error("could not decode the sprite sheet: {0}")]
+    Decode(#[from] png::DecodingError),
+
+    #[error("a {width}x{height} sprite sheet can not be split into {columns}x{rows} sprites")]
+    Grid {
+        width: u32,
+        height: u32,
+        columns: u32,
+        rows: u32,
+    },
+
+    #[error("the sprite sheet is {width}x{height}, the gpu allows at most {max}x{max}")]
+    TooLarge {
+        width: u32,
+        height: u32,
+        max: u32,
+    },
+}
+
+/// a decoded sheet, rgba8 with straight alpha
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpriteSheet {
+    /// where it was loaded from, if it was
+    pub path: Option<PathBuf>,
+    pub width: u32,
+    pub height: u32,
+    /// columns and rows
+    pub grid: [u32; 2],
+    pub data: Vec<u8>,
+}
+
+impl SpriteSheet {
+    /// the sheet `config` asks for, `None` without one
+    pub fn for_config(config: &SnowConfig) -> Result<Option<Self>, SpriteError> {
+        config.sprite_sheet.as_deref()
+            .map(|path| Self::load(path, config.sprite_grid))
+        .transpose()
+    }
+
+    pub fn load(path: &Path, grid: [u32; 2]) -> Result<Self, SpriteError> {
+        let io = |source| SpriteError::Io { path: path.to_path_buf(), source };
+        let file = std::fs::File::open(path).map_err(io)?;
+        let sheet = Self::decode(std::io::BufReader::new(file), grid)?;
+        tracing::info!("loaded {} sprites from {path:?}", sheet.count());
+        Ok(Self { path: Some(path.to_path_buf()), ..sheet })
+    }
+
+    /// any png, the pixels are converted to rgba8
+    pub fn decode(r: impl Read, grid: [u32; 2]) -> Result<Self, SpriteError> {
+        let mut decoder = png::Decoder::new(r);
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+        let mut reader = decoder.read_info()?;
+        let mut buf = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buf)?;
+        buf.truncate(info.buffer_size());
+
+        let data = match info.color_type {
+            png::ColorType::Rgba => buf,
+            png::ColorType::Rgb => buf.chunks(3).flat_map(|v| [v[0], v[1], v[2], 255]).collect(),
+            png::ColorType::GrayscaleAlpha => buf.chunks(2).flat_map(|v| [v[0], v[0], v[0], v[1]]).collect(),
+            // palettes are expanded to rgb by the transformations
+            png::ColorType::Grayscale | png::ColorType::Indexed => {
+                buf.iter().flat_map(|v| [*v, *v, *v, 255]).collect()
+            },
+        };
+
+        let [columns, rows] = grid;
+        let (width, height) = (info.width, info.height);
+        if columns == 0 || rows == 0 || width % columns != 0 || height % rows != 0 {
+            return Err(SpriteError::Grid { width, height, columns, rows });
+        }
+        Ok(Self { path: None, width, height, grid, data })
+    }
+
+    pub fn count(&self) -> u32 {
+        self.grid[0] * self.grid[1]
+    }
+
+    /// whether the gpu can hold it
+    pub fn check_size(&self, limits: &wgpu::Limits) -> Result<(), SpriteError> {
+        let max = limits.max_texture_dimension_2d;
+        if self.width > max || self.height > max {
+            return Err(SpriteError::TooLarge { width: self.width, height: self.height, max });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::SnowConfig,
+        headless::{self, HeadlessState},
+        snow::BuildError,
+    };
+
+    fn png(width: u32, height: u32, color: png::ColorType, data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut encoder = png::Encoder::new(&mut out, width, height);
+        encoder.set_color(color);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.write_header().unwrap().write_image_data(data).unwrap();
+        out
+    }
+
+    #[test]
+    fn validates_the_grid() {
+        let rgba = png(4, 2, png::ColorType::Rgba, &[200; 4 * 2 * 4]);
+        let sheet = SpriteSheet::decode(&rgba[..], [2, 1]).unwrap();
+        assert_eq!((sheet.width, sheet.height, sheet.count()), (4, 2, 2));
+        assert_eq!(sheet.data.len(), 4 * 2 * 4);
+
+        let invalid = |grid| matches!(
+            SpriteSheet::decode(&rgba[..], grid),
+            Err(SpriteError::Grid { .. }),
+        );
+        assert!(invalid([3, 1]));
+        assert!(invalid([0, 1]));
+        assert!(invalid([4, 4]));
+
+        let gray = png(2, 2, png::ColorType::Grayscale, &[0, 50, 100, 150]);
+        let sheet = SpriteSheet::decode(&gray[..], [1, 1]).unwrap();
+        assert_eq!(&sheet.data[4..8], &[50, 50, 50, 255]);
+
+        assert!(matches!(SpriteSheet::decode(&b"not a png"[..], [1, 1]), Err(SpriteError::Decode(_))));
+        let missing = Path::new("/nonexistent/sprites.png");
+        assert!(matches!(SpriteSheet::load(missing, [1, 1]), Err(SpriteError::Io { .. })));
+    }
+
+    #[test]
+    fn draws_sprites() {
+        let Some((device, queue, sim)) = headless::test_device() else { return };
+        let config = SnowConfig { particle_count: 200, flake_size: [0.05, 0.05], ..Default::default() };
+        let mut state = HeadlessState::new(&device, 64, 64, &config, 0x5eed, sim);
+        let is_red = |v: &[u8]| v[0] > 200 && v[1] < 50 && v[3] > 200;
+        let frame = state.render(&device, &queue, 0.0);
+        assert!(!frame.data.chunks(4).any(is_red));
+
+        // one red and one fully transparent sprite
+        let mut data = [255, 0, 0, 255].repeat(8 * 8);
+        data.extend([0; 8 * 8 * 4]);
+        let sheet = SpriteSheet::decode(&png(8, 16, png::ColorType::Rgba, &data)[..], [1, 2]).unwrap();
+        state.snow_mut().set_sprites(&device, &queue, Some(&sheet)).unwrap();
+        let frame = state.render(&device, &queue, 0.0);
+        assert!(frame.data.chunks(4).any(is_red));
+
+        let limits = wgpu::Limits { max_texture_dimension_2d: 8, ..Default::default() };
+        assert!(matches!(sheet.check_size(&limits), Err(SpriteError::TooLarge { .. })));
+        let missing = SnowConfig { sprite_sheet: Some("/nonexistent.png".into()), ..config };
+        assert!(matches!(
+            state.snow_mut().load_sprites(&device, &queue, &missing),
+            Err(BuildError::Sprites(SpriteError::Io { .. })),
+        ));
+    }
+}
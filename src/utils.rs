@@ -1,5 +1,5 @@
 
-use std::{marker::PhantomData, ops::{Deref, DerefMut}, num::NonZeroU64};
+use std::{marker::PhantomData, ops::{Deref, DerefMut}, num::NonZeroU64, path::Path, time::SystemTime};
 
 use bytemuck::Pod;
 use wgpu::util::DeviceExt;
@@ -42,5 +42,7 @@ impl<T: Pod> UniformBuffer<T> {
     }
 }
 
-
-
+/// `None` if there is no file at `path`
+pub fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|v| v.modified()).ok()
+}
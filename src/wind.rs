@@ -0,0 +1,143 @@
+//! the wind: a base wind, turbulence the shaders sample per flake and
+//! gusts that are scheduled here and passed on in `FrameData`
+
+use rand::prelude::*;
+use rand::rngs::StdRng;
+
+use crate::config::SnowConfig;
+
+
+/// seconds a gust takes to build up
+const ATTACK: f32 = 0.8;
+/// seconds for a gust to die down to a third
+const DECAY: f32 = 2.5;
+/// after this many `DECAY`s a gust is over
+const DECAY_END: f32 = 4.0;
+/// how far a gust turns away from the base wind, in radians
+const GUST_SPREAD: f32 = 0.4;
+
+/// the strength of a gust `t` seconds after it started, in [0, 1]
+fn envelope(t: f32) -> f32 {
+    if t < 0.0 {
+        0.0
+    } else if t < ATTACK {
+        let x = t / ATTACK;
+        x * x * (3.0 - 2.0 * x)
+    } else {
+        (-(t - ATTACK) / DECAY).exp()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Gust {
+    start: f32,
+    /// at its peak
+    force: [f32; 2],
+}
+
+/// schedules gusts on top of the base wind
+#[derive(Debug, Clone)]
+pub struct Wind {
+    base: [f32; 2],
+    strength: f32,
+    interval: [f32; 2],
+    rng: StdRng,
+    next_gust: f32,
+    gust: Option<Gust>,
+}
+
+impl Wind {
+    pub fn new(config: &SnowConfig, seed: u64) -> Self {
+        let mut wind = Self {
+            base: config.wind,
+            strength: config.gust_strength,
+            interval: config.gust_interval,
+            rng: StdRng::seed_from_u64(seed),
+            next_gust: 0.0,
+            gust: None,
+        };
+        wind.next_gust = wind.pause();
+        wind
+    }
+
+    /// a running gust dies down as it would have
+    pub fn set_config(&mut self, config: &SnowConfig) {
+        self.base = config.wind;
+        self.strength = config.gust_strength;
+        self.interval = config.gust_interval;
+    }
+
+    /// seconds until the next gust
+    fn pause(&mut self) -> f32 {
+        let [min, max] = self.interval;
+        min + (max - min) * self.rng.gen::<f32>()
+    }
+
+    /// along the base wind, or left or right without one
+    fn gust_force(&mut self) -> [f32; 2] {
+        let [x, y] = self.base;
+        let len = x.hypot(y);
+        let dir = if len > 0.0 {
+            [x / len, y / len]
+        } else if self.rng.gen() {
+            [1.0, 0.0]
+        } else {
+            [-1.0, 0.0]
+        };
+        let (sin, cos) = (self.rng.gen_range(-1.0..=1.0) * GUST_SPREAD).sin_cos();
+        let force = self.strength * self.rng.gen_range(0.5..=1.0);
+        [(dir[0] * cos - dir[1] * sin) * force, (dir[0] * sin + dir[1] * cos) * force]
+    }
+
+    /// the wind at `time` seconds and how strong the current gust is, in [0, 1]
+    pub fn sample(&mut self, time: f32) -> ([f32; 2], f32) {
+        if self.gust.is_some_and(|v| time - v.start > ATTACK + DECAY * DECAY_END) {
+            self.gust = None;
+        }
+        if self.gust.is_none() && time >= self.next_gust {
+            let force = self.gust_force();
+            self.gust = Some(Gust { start: time, force });
+            self.next_gust = time + self.pause();
+        }
+
+        let Some(gust) = self.gust else { return (self.base, 0.0) };
+        let strength = envelope(time - gust.start);
+        let [x, y] = self.base;
+        ([x + gust.force[0] * strength, y + gust.force[1] * strength], strength)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gusts_rise_and_decay() {
+        let config = SnowConfig {
+            wind: [0.2, 0.0],
+            gust_strength: 1.0,
+            gust_interval: [2.0, 2.0],
+            ..Default::default()
+        };
+        let mut wind = Wind::new(&config, 1);
+        assert_eq!(wind.sample(0.0), ([0.2, 0.0], 0.0));
+        assert_eq!(wind.sample(1.9), ([0.2, 0.0], 0.0));
+
+        let samples = (0..200).map(|i| wind.sample(2.0 + i as f32 * 0.1)).collect::<Vec<_>>();
+        let strength = samples.iter().map(|v| v.1).collect::<Vec<_>>();
+        let peak = strength.iter().copied().fold(0.0, f32::max);
+        assert!(peak > 0.95);
+        // up during the attack, down after
+        assert!(strength[..8].windows(2).all(|v| v[0] <= v[1]));
+        assert!(strength[9..30].windows(2).all(|v| v[0] >= v[1]));
+        // mostly along the base wind
+        let (gust, _) = samples[8];
+        assert!(gust[0] > 0.5 && gust[1].abs() < gust[0]);
+        // and the next one comes after the last one ended
+        assert!(strength[100..].iter().any(|v| *v > 0.95));
+
+        let calm = SnowConfig { gust_strength: 0.0, ..config };
+        let mut wind = Wind::new(&calm, 1);
+        assert!((0..100).all(|i| wind.sample(i as f32 * 0.5).0 == [0.2, 0.0]));
+    }
+}
@@ -0,0 +1,278 @@
+use std::collections::{HashMap, VecDeque};
+
+
+/// a top level window of some other application,
+/// in global screen coordinates (origin at the top left).
+/// the unit depends on the platform, see `platform::window_scale`
+#[derive(Debug, Clone, PartialEq)]
+pub struct AppWindow {
+    pub owner_name: Option<String>,
+    pub name: Option<String>,
+    pub pos: (f64, f64),
+    pub dim: (f64, f64),
+    pub layer: i64,
+    pub number: i64,
+}
+
+/// a rect on the desktop, in the same coordinates as `AppWindow`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Region {
+    pub origin: (f64, f64),
+    pub dim: (f64, f64),
+}
+
+impl Region {
+    /// the smallest region covering both
+    pub fn union(self, other: Self) -> Self {
+        let min = (self.origin.0.min(other.origin.0), self.origin.1.min(other.origin.1));
+        let max = (
+            (self.origin.0 + self.dim.0).max(other.origin.0 + other.dim.0),
+            (self.origin.1 + self.dim.1).max(other.origin.1 + other.dim.1),
+        );
+        Self { origin: min, dim: (max.0 - min.0, max.1 - min.1) }
+    }
+
+    pub fn aspect(self) -> f32 {
+        (self.dim.0 / self.dim.1) as f32
+    }
+
+    /// `pos` in the simulation space covering this region, [-1, 1] with y up
+    pub fn to_sim(self, pos: (f64, f64)) -> [f32; 2] {
+        [
+            ((pos.0 - self.origin.0) / self.dim.0 * 2.0 - 1.0) as f32,
+            -((pos.1 - self.origin.1) / self.dim.1 * 2.0 - 1.0) as f32,
+        ]
+    }
+
+    /// `window` in [0, 1] with the origin at the top left, like `RectInstance`
+    pub fn to_unit(self, window: &AppWindow) -> ([f32; 2], [f32; 2]) {
+        (
+            [
+                ((window.pos.0 - self.origin.0) / self.dim.0) as f32,
+                ((window.pos.1 - self.origin.1) / self.dim.1) as f32,
+            ],
+            [
+                (window.dim.0 / self.dim.0) as f32,
+                (window.dim.1 / self.dim.1) as f32,
+            ],
+        )
+    }
+}
+
+/// something that can list the windows currently on screen
+pub trait WindowSource {
+    fn windows(&mut self) -> Vec<AppWindow>;
+}
+
+/// plays back a fixed list of window snapshots, one per call.
+/// once all snapshots are used up the last one is repeated
+#[derive(Debug, Default)]
+pub struct ScriptedWindows {
+    frames: VecDeque<Vec<AppWindow>>,
+    last: Vec<AppWindow>,
+}
+
+impl ScriptedWindows {
+    pub fn new(frames: impl IntoIterator<Item = Vec<AppWindow>>) -> Self {
+        Self {
+            frames: frames.into_iter().collect(),
+            last: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, frame: Vec<AppWindow>) {
+        self.frames.push_back(frame);
+    }
+}
+
+impl WindowSource for ScriptedWindows {
+    fn windows(&mut self) -> Vec<AppWindow> {
+        if let Some(frame) = self.frames.pop_front() {
+            self.last = frame;
+        }
+        self.last.clone()
+    }
+}
+
+/// the row of a window that closed, moved or was resized,
+/// its snow falls off where the window was
+#[derive(Debug, Clone, PartialEq)]
+pub struct Shed {
+    pub row: u32,
+    pub from: AppWindow,
+    /// `None` if it closed
+    pub to: Option<AppWindow>,
+}
+
+/// gives every window a row in the cap heightfield that stays the same
+/// while the window is open, wherever it is in the stacking order
+#[derive(Debug)]
+pub struct CapSlots {
+    windows: HashMap<i64, (u32, AppWindow)>,
+    free: Vec<u32>,
+}
+
+impl CapSlots {
+    /// uses the rows `1..=count`, row 0 is the bottom of the screen
+    pub fn new(count: u32) -> Self {
+        Self {
+            windows: HashMap::new(),
+            free: (1..=count).rev().collect(),
+        }
+    }
+
+    /// the rows of `windows`, tracked by `AppWindow::number`. windows
+    /// past the capacity get none. also returns the rows whose snow has
+    /// to go, because their window was closed, moved or resized
+    pub fn update(&mut self, windows: &[AppWindow]) -> (Vec<u32>, Vec<Shed>) {
+        let mut shed = Vec::new();
+        self.windows.retain(|number, (row, last)| {
+            let open = windows.iter().any(|v| v.number == *number);
+            if !open {
+                shed.push(Shed { row: *row, from: last.clone(), to: None });
+            }
+            open
+        });
+        self.free.extend(shed.iter().map(|v| v.row));
+
+        let rows = windows.iter().map_while(|window| {
+            match self.windows.get_mut(&window.number) {
+                Some((row, last)) => {
+                    // dragging a window shakes the snow off
+                    if last.pos != window.pos || last.dim != window.dim {
+                        shed.push(Shed { row: *row, from: last.clone(), to: Some(window.clone()) });
+                    }
+                    *last = window.clone();
+                    Some(*row)
+                },
+                None => {
+                    let row = self.free.pop()?;
+                    self.windows.insert(window.number, (row, window.clone()));
+                    Some(row)
+                },
+            }
+        }).collect();
+
+        (rows, shed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::SnowConfig,
+        cursor::NoCursor,
+        headless,
+        snow::{Simulation, Snow, CAP_COLUMNS},
+    };
+
+    const STEPS: usize = 120;
+
+    fn window(number: i64, pos: (f64, f64)) -> AppWindow {
+        AppWindow {
+            owner_name: None,
+            name: None,
+            pos,
+            dim: (100.0, 100.0),
+            layer: 0,
+            number,
+        }
+    }
+
+    #[test]
+    fn cap_rows_follow_windows() {
+        let mut slots = CapSlots::new(2);
+        let (rows, shed) = slots.update(&[window(7, (0.0, 0.0)), window(3, (10.0, 0.0))]);
+        assert_eq!(rows, [1, 2]);
+        assert!(shed.is_empty());
+
+        // restacked, same rows
+        let (rows, shed) = slots.update(&[window(3, (10.0, 0.0)), window(7, (0.0, 0.0))]);
+        assert_eq!(rows, [2, 1]);
+        assert!(shed.is_empty());
+
+        // moved
+        let (rows, shed) = slots.update(&[window(3, (20.0, 0.0)), window(7, (0.0, 0.0))]);
+        assert_eq!(rows, [2, 1]);
+        assert_eq!(shed, [Shed { row: 2, from: window(3, (10.0, 0.0)), to: Some(window(3, (20.0, 0.0))) }]);
+
+        // closed, the row is reused and no row is left for the last window
+        let (rows, shed) = slots.update(&[window(3, (20.0, 0.0)), window(9, (0.0, 0.0)), window(11, (0.0, 0.0))]);
+        assert_eq!(rows, [2, 1]);
+        assert_eq!(shed, [Shed { row: 1, from: window(7, (0.0, 0.0)), to: None }]);
+    }
+
+    #[test]
+    fn scripted_windows_play_back_frames() {
+        let mut source = ScriptedWindows::default();
+        assert!(source.windows().is_empty());
+
+        let mut source = ScriptedWindows::new([vec![window(1, (0.0, 0.0))], vec![]]);
+        source.push(vec![window(1, (5.0, 0.0)), window(2, (0.0, 0.0))]);
+        assert_eq!(source.windows(), [window(1, (0.0, 0.0))]);
+        assert!(source.windows().is_empty());
+        // the last one stays
+        for _ in 0..2 {
+            assert_eq!(source.windows(), [window(1, (5.0, 0.0)), window(2, (0.0, 0.0))]);
+        }
+    }
+
+    #[test]
+    fn simulation_sheds_the_caps_of_moved_windows() {
+        let Some((device, queue, sim)) = headless::test_device() else { return };
+        let config = SnowConfig::default();
+        let snow = Snow::new(&device, headless::FORMAT, &config, 1.0, 0x5eed, sim);
+        // top edge at y = 0.5, x in [-0.5, 0.5], then dragged to the bottom right
+        let before = AppWindow { dim: (500.0, 500.0), ..window(1, (250.0, 250.0)) };
+        let after = AppWindow { pos: (600.0, 600.0), ..before.clone() };
+        let script = std::iter::repeat_n(vec![before], STEPS)
+            .chain([vec![after]]);
+        let region = Region { origin: (0.0, 0.0), dim: (1000.0, 1000.0) };
+        let mut sim = Simulation::new(
+            &device, snow, &config, 0x5eed, region,
+            Box::new(ScriptedWindows::new(script)), Box::new(NoCursor),
+        );
+        let mut frame = |sim: &mut Simulation| {
+            sim.update(&device, &queue);
+            let frame_data = sim.snow_mut().frame_data_mut();
+            frame_data.dt = 1.0 / 60.0;
+            frame_data.gravity = [0.1, -200.0];
+            sim.step(&device, &queue);
+        };
+
+        for _ in 0..STEPS { frame(&mut sim) }
+        let row = CAP_COLUMNS..2 * CAP_COLUMNS;
+        let caps = sim.snow().read_caps(&device, &queue);
+        let columns = caps[row.clone()].iter().filter(|v| **v > 0).count();
+        assert!(columns > 0, "nothing settled on the window");
+
+        frame(&mut sim);
+        let caps = sim.snow().read_caps(&device, &queue);
+        assert!(caps[row].iter().all(|v| *v == 0));
+        // a flake for every column with snow, all thrown along with the window
+        let instances = sim.snow().read_instances(&device, &queue);
+        let on_top = instances.iter()
+            .filter(|v| (-0.5..=0.5).contains(&v.pos[0]) && v.pos[1] > 0.5 && v.pos[1] < 0.52)
+        .collect::<Vec<_>>();
+        let vel = on_top.iter().map(|v| v.vel).max_by(|a, b| a[0].total_cmp(&b[0])).unwrap();
+        assert!(vel[0] > 0.0 && vel[1] < 0.0, "{vel:?}");
+        assert_eq!(on_top.iter().filter(|v| v.vel == vel).count(), columns);
+    }
+
+    #[test]
+    fn regions_map_to_sim_space() {
+        let left = Region { origin: (0.0, 0.0), dim: (1920.0, 1080.0) };
+        let right = Region { origin: (1920.0, -200.0), dim: (1280.0, 1024.0) };
+        let desktop = left.union(right);
+        assert_eq!(desktop, Region { origin: (0.0, -200.0), dim: (3200.0, 1280.0) });
+
+        assert_eq!(desktop.to_sim((0.0, -200.0)), [-1.0, 1.0]);
+        assert_eq!(desktop.to_sim((3200.0, 1080.0)), [1.0, -1.0]);
+        assert_eq!(desktop.to_sim((1600.0, 440.0)), [0.0, 0.0]);
+
+        let (pos, dim) = desktop.to_unit(&window(1, (1920.0, -200.0)));
+        assert_eq!(pos, [0.6, 0.0]);
+        assert_eq!(dim, [100.0 / 3200.0, 100.0 / 1280.0]);
+    }
+}